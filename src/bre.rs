@@ -0,0 +1,161 @@
+//! Translation of POSIX basic regular expressions (BRE) into the syntax the
+//! `regex` crate understands, for `-G` / `--pattern-type=basic`.
+//!
+//! Supported translations:
+//!
+//! | BRE          | regex crate       | meaning                 |
+//! |--------------|-------------------|-------------------------|
+//! | `\(` `\)`    | `(` `)`           | group                   |
+//! | `\{m,n\}`    | `{m,n}`           | interval                |
+//! | `\+` `\?`    | `+` `?`           | repetition (GNU)        |
+//! | `\<` `\>`    | `\b{start}` `\b{end}` | word start / end (GNU) |
+//! | `(` `)`      | `\(` `\)`         | literal parenthesis     |
+//! | `{` `}`      | `\{` `\}`         | literal brace           |
+//! | `+` `?`      | `\+` `\?`         | literal character       |
+//! | leading `*`  | `\*`              | literal star            |
+//! | `[...]`      | `[...]`           | bracket expression, with `\` and `[` kept literal |
+//!
+//! As a GNU extension `\|` is alternation, which leaves a bare pipe literal.
+//! A `*` is literal where there's nothing for it to repeat: at the start of
+//! the pattern, or right after `^`, `\(` or `\|`. Everything else is passed
+//! through unchanged.
+
+/// Rewrites a BRE `pattern` into the equivalent `regex` crate pattern.
+pub fn translate(pattern: &str) -> String {
+    let mut translated = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    // True where a `*` would have nothing to repeat.
+    let mut at_start = true;
+
+    while let Some(c) = chars.next() {
+        let mut next_at_start = false;
+
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('(' | '|')) => {
+                    translated.push(escaped);
+                    next_at_start = true;
+                }
+                Some(escaped @ (')' | '{' | '}' | '+' | '?')) => translated.push(escaped),
+                Some('<') => translated.push_str(r"\b{start}"),
+                Some('>') => translated.push_str(r"\b{end}"),
+                Some(escaped) => {
+                    translated.push('\\');
+                    translated.push(escaped);
+                }
+                None => translated.push_str("\\\\"),
+            },
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' => {
+                translated.push('\\');
+                translated.push(c);
+            }
+            '*' if at_start => translated.push_str(r"\*"),
+            '^' if at_start => {
+                translated.push(c);
+                next_at_start = true;
+            }
+            '[' => translate_bracket(&mut chars, &mut translated),
+            _ => translated.push(c),
+        }
+
+        at_start = next_at_start;
+    }
+
+    translated
+}
+
+/// Copies a bracket expression whose opening `[` has already been consumed.
+/// In POSIX brackets a backslash is an ordinary character and a leading `]`
+/// is a member rather than the terminator.
+fn translate_bracket<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>, translated: &mut String) {
+    translated.push('[');
+
+    if chars.peek() == Some(&'^') {
+        chars.next();
+        translated.push('^');
+    }
+
+    if chars.peek() == Some(&']') {
+        chars.next();
+        translated.push_str("\\]");
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            ']' => {
+                translated.push(']');
+                return;
+            }
+            '[' if matches!(chars.peek(), Some(':' | '=' | '.')) => {
+                // Character class such as [:alpha:], copied through its closing `:]`.
+                let delimiter = chars.next().unwrap();
+                translated.push('[');
+                translated.push(delimiter);
+                let mut previous = delimiter;
+                for inner in chars.by_ref() {
+                    translated.push(inner);
+                    if inner == ']' && previous == delimiter {
+                        break;
+                    }
+                    previous = inner;
+                }
+            }
+            '\\' | '[' => {
+                translated.push('\\');
+                translated.push(c);
+            }
+            _ => translated.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_groups() {
+        assert_eq!(translate(r"\(foo\)"), "(foo)");
+    }
+
+    #[test]
+    fn test_translate_literal_parens() {
+        assert_eq!(translate("f(x)"), r"f\(x\)");
+    }
+
+    #[test]
+    fn test_translate_intervals() {
+        assert_eq!(translate(r"a\{2,3\}"), "a{2,3}");
+        assert_eq!(translate("a{2}"), r"a\{2\}");
+    }
+
+    #[test]
+    fn test_translate_passes_other_escapes_through() {
+        assert_eq!(translate(r"a\.b\*"), r"a\.b\*");
+    }
+
+    #[test]
+    fn test_translate_gnu_operators() {
+        assert_eq!(translate(r"cat\|dog"), "cat|dog");
+        assert_eq!(translate(r"a\+b\?"), "a+b?");
+        assert_eq!(translate(r"\<word\>"), r"\b{start}word\b{end}");
+    }
+
+    #[test]
+    fn test_translate_literal_operators() {
+        assert_eq!(translate("a|b+c?"), r"a\|b\+c\?");
+    }
+
+    #[test]
+    fn test_translate_leading_star_is_literal() {
+        assert_eq!(translate("*a*"), r"\*a*");
+        assert_eq!(translate("^*a"), r"^\*a");
+        assert_eq!(translate(r"\(*a\)\|*b"), r"(\*a)|\*b");
+    }
+
+    #[test]
+    fn test_translate_bracket_expression() {
+        assert_eq!(translate(r"[]a\]"), r"[\]a\\]");
+        assert_eq!(translate("[^[:digit:](]"), r"[^[:digit:](]");
+    }
+}
@@ -0,0 +1,120 @@
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+
+/// When to highlight output with ANSI escape sequences.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal
+    #[default]
+    Auto,
+    /// Always, even into a pipe or file
+    Always,
+    /// Never
+    Never,
+}
+
+/// SGR parameters (the `01;31` in `ESC[01;31m`) for each highlighted part of
+/// the output. An empty string leaves that part uncolored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Colors {
+    pub matched: String,
+    pub file_name: String,
+    pub line_number: String,
+    pub byte_offset: String,
+    pub separator: String,
+    /// Matched text a `--replace` preview is taking out.
+    pub removed: String,
+    /// Text a `--replace` preview is putting in.
+    pub inserted: String,
+}
+
+impl Default for Colors {
+    /// GNU grep's defaults: bold red matches, magenta file names, green line
+    /// numbers and cyan separators. A `--replace` preview strikes out the old
+    /// text in red and shows the new text in green.
+    fn default() -> Self {
+        Colors {
+            matched: String::from("01;31"),
+            file_name: String::from("35"),
+            line_number: String::from("32"),
+            byte_offset: String::from("32"),
+            separator: String::from("36"),
+            removed: String::from("09;31"),
+            inserted: String::from("32"),
+        }
+    }
+}
+
+impl Colors {
+    /// Applies a `GREP_COLORS` value such as `ms=01;32:fn=34:se=` on top of
+    /// the defaults. `mt` sets the match color; capabilities that don't
+    /// apply here (`sl`, `cx`, `rv`, `ne`, ...) and malformed entries are
+    /// ignored rather than rejected, as GNU grep does.
+    pub fn from_grep_colors(spec: &str) -> Self {
+        let mut colors = Colors::default();
+
+        for capability in spec.split(':') {
+            let Some((name, value)) = capability.split_once('=') else {
+                continue;
+            };
+
+            if !value.bytes().all(|b| b.is_ascii_digit() || b == b';') {
+                continue;
+            }
+
+            let field = match name {
+                "ms" | "mt" => &mut colors.matched,
+                "fn" => &mut colors.file_name,
+                "ln" => &mut colors.line_number,
+                "bn" => &mut colors.byte_offset,
+                "se" => &mut colors.separator,
+                _ => continue,
+            };
+            *field = value.to_string();
+        }
+
+        colors
+    }
+}
+
+/// Wraps `text` in the escape sequences for `sgr`, clearing to the end of the
+/// line after each sequence like grep does so backgrounds don't bleed.
+pub fn paint<'a>(sgr: &str, text: &'a str) -> Cow<'a, str> {
+    if sgr.is_empty() || text.is_empty() {
+        return Cow::Borrowed(text);
+    }
+
+    Cow::Owned(format!("\x1b[{}m\x1b[K{}\x1b[m\x1b[K", sgr, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint() {
+        assert_eq!(paint("01;31", "hit"), "\x1b[01;31m\x1b[Khit\x1b[m\x1b[K");
+    }
+
+    #[test]
+    fn test_from_grep_colors() {
+        let colors = Colors::from_grep_colors("ms=01;32:fn=34:se=:sl=1:rv:ln=x");
+
+        assert_eq!(colors.matched, "01;32");
+        assert_eq!(colors.file_name, "34");
+        assert_eq!(colors.separator, "");
+        assert_eq!(colors.line_number, "32");
+    }
+
+    #[test]
+    fn test_from_grep_colors_mt() {
+        assert_eq!(Colors::from_grep_colors("mt=7").matched, "7");
+        assert_eq!(Colors::from_grep_colors(""), Colors::default());
+    }
+
+    #[test]
+    fn test_paint_empty_sgr_is_plain() {
+        assert!(matches!(paint("", "hit"), Cow::Borrowed("hit")));
+    }
+}
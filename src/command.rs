@@ -0,0 +1,159 @@
+//! Reading a file through an external program, for `--search-zip` and `--pre`.
+
+use std::ffi::OsStr;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// Decompressors by file extension, run with the file's path appended. Like
+/// `zgrep` (and ripgrep), these are the usual command-line tools rather
+/// than built-in codecs, so each format needs its tool installed.
+const DECOMPRESSORS: &[(&str, &[&str])] = &[
+    ("gz", &["gzip", "-d", "-c"]),
+    ("tgz", &["gzip", "-d", "-c"]),
+    ("bz2", &["bzip2", "-d", "-c"]),
+    ("tbz2", &["bzip2", "-d", "-c"]),
+    ("xz", &["xz", "-d", "-c"]),
+    ("txz", &["xz", "-d", "-c"]),
+    ("lzma", &["xz", "--format=lzma", "-d", "-c"]),
+    ("lz4", &["lz4", "-d", "-c"]),
+    ("zst", &["zstd", "-q", "-d", "-c"]),
+    ("zstd", &["zstd", "-q", "-d", "-c"]),
+];
+
+/// The command that decompresses `path`, judging by its extension.
+pub(crate) fn decompressor(path: &Path) -> Option<&'static [&'static str]> {
+    let extension = path.extension()?;
+
+    DECOMPRESSORS
+        .iter()
+        .find(|(known, _)| OsStr::new(known) == extension)
+        .map(|(_, command)| *command)
+}
+
+/// The standard output of a running command. A command that exits
+/// unsuccessfully turns into a read error carrying what it wrote to stderr.
+pub(crate) struct CommandReader {
+    child: Child,
+    stdout: ChildStdout,
+    /// Collects stderr as it's written, so a command with a lot to say there
+    /// can't fill the pipe and stall before its stdout is done.
+    stderr: Option<JoinHandle<io::Result<String>>>,
+    program: String,
+}
+
+impl CommandReader {
+    /// Starts `command`, which must not be empty, with `path` as its last
+    /// argument and `stdin` as its standard input.
+    pub(crate) fn spawn(command: &[&str], path: &Path, stdin: Stdio) -> io::Result<Self> {
+        let program = command[0].to_string();
+        let mut child = Command::new(&program)
+            .args(&command[1..])
+            .arg(path)
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", program, err)))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+        let stderr = thread::spawn(move || {
+            let mut stderr = String::new();
+            stderr_pipe.read_to_string(&mut stderr)?;
+            Ok(stderr)
+        });
+
+        Ok(CommandReader { child, stdout, stderr: Some(stderr), program })
+    }
+}
+
+impl Read for CommandReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read > 0 || buf.is_empty() {
+            return Ok(read);
+        }
+
+        let stderr = match self.stderr.take() {
+            Some(reader) => reader.join().map_err(|_| io::Error::other("reading stderr panicked"))??,
+            None => String::new(),
+        };
+
+        let status = self.child.wait()?;
+        if status.success() {
+            return Ok(0);
+        }
+
+        let message = match stderr.trim() {
+            "" => format!("{} exited with {}", self.program, status),
+            stderr => stderr.to_string(),
+        };
+        Err(io::Error::other(message))
+    }
+}
+
+impl Drop for CommandReader {
+    /// A search can stop reading early (`-l`, `-m`), so don't leave the
+    /// command blocked on a full pipe.
+    fn drop(&mut self) {
+        if matches!(self.child.try_wait(), Ok(None)) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompressor_by_extension() {
+        assert_eq!(decompressor(Path::new("logs/app.log.gz")), Some(&["gzip", "-d", "-c"][..]));
+        assert_eq!(decompressor(Path::new("data.tar.zst")).map(|command| command[0]), Some("zstd"));
+        assert_eq!(decompressor(Path::new("notes.txt")), None);
+        assert_eq!(decompressor(Path::new("gz")), None);
+    }
+
+    #[test]
+    fn test_command_reader_reads_stdout() -> io::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), "hello\n")?;
+
+        let mut output = String::new();
+        CommandReader::spawn(&["cat"], file.path(), Stdio::null())?.read_to_string(&mut output)?;
+
+        assert_eq!(output, "hello\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_reader_feeds_stdin() -> io::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), "a\nb\n")?;
+
+        // `wc -l PATH` would print the path too, so this shows stdin was read.
+        let mut output = String::new();
+        CommandReader::spawn(&["sh", "-c", "wc -l", "sh"], file.path(), Stdio::from(std::fs::File::open(file.path())?))?
+            .read_to_string(&mut output)?;
+
+        assert_eq!(output.trim(), "2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_reader_reports_failure() {
+        let mut output = String::new();
+        let err = CommandReader::spawn(&["cat"], Path::new("/nonexistent/file"), Stdio::null()).unwrap().read_to_string(&mut output).unwrap_err();
+
+        assert!(err.to_string().contains("/nonexistent/file"), "{}", err);
+    }
+
+    #[test]
+    fn test_command_reader_missing_program() {
+        let err = CommandReader::spawn(&["rusty-grep-no-such-program"], Path::new("x"), Stdio::null()).err().unwrap();
+
+        assert!(err.to_string().starts_with("rusty-grep-no-such-program: "));
+    }
+}
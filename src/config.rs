@@ -0,0 +1,68 @@
+//! Default arguments read from a config file before the command line, so
+//! options like `--smart-case` don't need a shell alias.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the config file, overriding the default location.
+pub const CONFIG_ENV_VAR: &str = "RUSTY_GREP_CONFIG";
+
+/// Where the config file is: `$RUSTY_GREP_CONFIG` when set, otherwise
+/// `rusty_grep/config` under `$XDG_CONFIG_HOME` or `~/.config`. Setting
+/// `$RUSTY_GREP_CONFIG` to nothing turns the config file off, so scripts and
+/// tests don't pick up the user's defaults. The flag says whether the
+/// location was asked for explicitly, in which case a missing file is worth
+/// a warning.
+pub fn config_path() -> Option<(PathBuf, bool)> {
+    match env::var_os(CONFIG_ENV_VAR) {
+        Some(path) if path.is_empty() => return None,
+        Some(path) => return Some((PathBuf::from(path), true)),
+        None => {}
+    }
+
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some((config_home.join("rusty_grep").join("config"), false))
+}
+
+/// Reads the arguments in the config file at `path`.
+pub fn read_config<P: AsRef<Path>>(path: P) -> io::Result<Vec<OsString>> {
+    Ok(parse_config(&fs::read_to_string(path)?))
+}
+
+/// Splits config text into arguments: one per line, exactly as it would be
+/// typed after the program name, so `--type-add=web:*.{html,css}` needs no
+/// quoting. Surrounding whitespace, blank lines and `#` comments are skipped.
+pub fn parse_config(text: &str) -> Vec<OsString> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(OsString::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let text = "# defaults\n--smart-case\n\n  --color=auto  \n--type-add=web:*.{html,css}\n";
+
+        assert_eq!(parse_config(text), ["--smart-case", "--color=auto", "--type-add=web:*.{html,css}"]);
+    }
+
+    #[test]
+    fn test_read_config_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let err = read_config(dir.path().join("config")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,126 @@
+//! Transcoding input to UTF-8 for `--encoding`, so text that isn't UTF-8
+//! (UTF-16 logs written on Windows, Latin-1 files) can still be matched.
+
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use encoding_rs_io::DecodeReaderBytesBuilder;
+
+/// A character encoding input can be read as: anything with a WHATWG label,
+/// such as `utf-16le`, `latin1` or `shift_jis`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Encoding(&'static encoding_rs::Encoding);
+
+impl Encoding {
+    pub const UTF_8: Encoding = Encoding(encoding_rs::UTF_8);
+
+    /// The encoding `label` names, ignoring case and surrounding whitespace
+    /// as the WHATWG Encoding Standard does.
+    pub fn for_label(label: &str) -> Option<Encoding> {
+        encoding_rs::Encoding::for_label(label.as_bytes()).map(Encoding)
+    }
+
+    /// The encoding's canonical name, e.g. `windows-1252` for `latin1`.
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Encoding::for_label(s).ok_or_else(|| format!("'{}' is not a known encoding label", s))
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The encoding a byte order mark at the start of `bytes` announces, and how
+/// long the mark is.
+pub(crate) fn sniff_bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    encoding_rs::Encoding::for_bom(bytes).map(|(encoding, len)| (Encoding(encoding), len))
+}
+
+/// Wraps a reader of `encoding` text and yields it as UTF-8. Malformed input
+/// becomes U+FFFD rather than an error, as invalid UTF-8 does elsewhere. The
+/// caller deals with byte order marks.
+pub(crate) fn decode_reader<R: Read>(inner: R, encoding: Encoding) -> impl Read {
+    DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding.0))
+        .bom_sniffing(false)
+        .build(inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn decode_all(label: &str, bytes: &[u8]) -> String {
+        let mut text = String::new();
+        decode_reader(bytes, label.parse().unwrap()).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!("UTF-16LE".parse::<Encoding>().unwrap().name(), "UTF-16LE");
+        assert_eq!("latin1".parse::<Encoding>().unwrap().name(), "windows-1252");
+        assert_eq!(" sjis ".parse::<Encoding>().unwrap().name(), "Shift_JIS");
+        assert!("klingon".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn test_sniff_bom() {
+        assert_eq!(sniff_bom(b"\xEF\xBB\xBFhi"), Some((Encoding::UTF_8, 3)));
+        assert_eq!(sniff_bom(b"\xFF\xFEh\0"), Encoding::for_label("utf-16le").map(|encoding| (encoding, 2)));
+        assert_eq!(sniff_bom(b"\xFE\xFF\0h"), Encoding::for_label("utf-16be").map(|encoding| (encoding, 2)));
+        assert_eq!(sniff_bom(b"hi"), None);
+        assert_eq!(sniff_bom(b""), None);
+    }
+
+    #[test]
+    fn test_decode_utf16() {
+        let le: Vec<u8> = "héllo 😀\n".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = "héllo 😀\n".encode_utf16().flat_map(u16::to_be_bytes).collect();
+
+        assert_eq!(decode_all("utf-16le", &le), "héllo 😀\n");
+        assert_eq!(decode_all("utf-16be", &be), "héllo 😀\n");
+        assert_eq!(decode_all("utf-16le", b"a\0b"), "a\u{FFFD}");
+    }
+
+    #[test]
+    fn test_decode_single_and_multi_byte_encodings() {
+        assert_eq!(decode_all("windows-1252", b"caf\xe9 \x80 \x93hi\x94"), "café € \u{201C}hi\u{201D}");
+        assert_eq!(decode_all("shift_jis", b"\x82\xa0"), "あ");
+    }
+
+    /// Hands out three bytes per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.0.len().min(buf.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_decode_reader_across_reads() {
+        let text = "ü😀".repeat(1000);
+        let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+        let mut decoded = String::new();
+        decode_reader(Trickle(&utf16), Encoding::for_label("utf-16le").unwrap()).read_to_string(&mut decoded).unwrap();
+
+        assert_eq!(decoded, text);
+    }
+}
@@ -0,0 +1,178 @@
+//! `--json` output: JSON Lines messages in the schema `rg --json` uses, so
+//! editors and tools that already read ripgrep's output can read ours.
+
+use std::io::{self, Write};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::{FileSummary, SearchSummary};
+
+/// The messages for one file: `begin` ahead of its first line, a `match` or
+/// `context` message per line, and `end` with its stats. Files that write no
+/// lines write no messages at all, as with ripgrep.
+pub(crate) struct JsonFile<'a> {
+    path: &'a str,
+    started: Instant,
+    begun: bool,
+    bytes_printed: u64,
+}
+
+impl<'a> JsonFile<'a> {
+    pub(crate) fn new(path: &'a str) -> Self {
+        JsonFile { path, started: Instant::now(), begun: false, bytes_printed: 0 }
+    }
+
+    /// Writes a `match` or `context` message. `lines` is the line with its
+    /// terminator and `submatches` are byte ranges within it.
+    pub(crate) fn write_line<W: Write>(
+        &mut self,
+        out: &mut W,
+        kind: &str,
+        lines: &str,
+        line_number: u32,
+        absolute_offset: u64,
+        submatches: &[Range<usize>],
+    ) -> io::Result<()> {
+        if !self.begun {
+            self.begun = true;
+            self.write(out, json!({"type": "begin", "data": {"path": {"text": self.path}}}))?;
+        }
+
+        let submatches: Vec<Value> = submatches
+            .iter()
+            .map(|span| json!({"match": {"text": &lines[span.clone()]}, "start": span.start, "end": span.end}))
+            .collect();
+
+        self.write(out, json!({
+            "type": kind,
+            "data": {
+                "path": {"text": self.path},
+                "lines": {"text": lines},
+                "line_number": line_number,
+                "absolute_offset": absolute_offset,
+                "submatches": submatches,
+            },
+        }))
+    }
+
+    /// Writes the `end` message, if the file wrote anything to end.
+    pub(crate) fn finish<W: Write>(mut self, out: &mut W, summary: &FileSummary) -> io::Result<()> {
+        if !self.begun {
+            return Ok(());
+        }
+
+        let stats = json!({
+            "elapsed": elapsed(self.started.elapsed()),
+            "searches": 1,
+            "searches_with_match": u64::from(summary.selected_lines > 0),
+            "bytes_searched": summary.bytes,
+            "bytes_printed": self.bytes_printed,
+            "matched_lines": summary.selected_lines,
+            "matches": summary.matches,
+        });
+        let path = self.path;
+        self.write(out, json!({"type": "end", "data": {"path": {"text": path}, "binary_offset": null, "stats": stats}}))
+    }
+
+    fn write<W: Write>(&mut self, out: &mut W, message: Value) -> io::Result<()> {
+        let line = format!("{}\n", message);
+        out.write_all(line.as_bytes())?;
+        self.bytes_printed += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Writes the closing `summary` message with totals for the whole search.
+pub(crate) fn write_summary<W: Write>(out: &mut W, summary: &SearchSummary, bytes_printed: u64, elapsed_total: Duration) -> io::Result<()> {
+    let message = json!({
+        "type": "summary",
+        "data": {
+            "elapsed_total": elapsed(elapsed_total),
+            "stats": {
+                "elapsed": elapsed(elapsed_total),
+                "searches": summary.files_searched,
+                "searches_with_match": summary.files_matched,
+                "bytes_searched": summary.total_bytes,
+                "bytes_printed": bytes_printed,
+                "matched_lines": summary.total_selected_lines,
+                "matches": summary.total_matches,
+            },
+        },
+    });
+
+    writeln!(out, "{}", message)
+}
+
+fn elapsed(duration: Duration) -> Value {
+    json!({
+        "secs": duration.as_secs(),
+        "nanos": duration.subsec_nanos(),
+        "human": format!("{:.6}s", duration.as_secs_f64()),
+    })
+}
+
+/// Passes writes through to `inner`, keeping count of the bytes written so
+/// the summary can report `bytes_printed`.
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    pub(crate) count: u64,
+}
+
+impl<W> CountingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        CountingWriter { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_file_writes_begin_once_and_end() -> io::Result<()> {
+        let mut out = Vec::new();
+        let mut file = JsonFile::new("src/a.rs");
+        file.write_line(&mut out, "match", "let foo = 1;\n", 3, 40, &[Range { start: 4, end: 7 }])?;
+        file.write_line(&mut out, "context", "}\n", 4, 53, &[])?;
+        file.finish(&mut out, &FileSummary { lines: 4, selected_lines: 1, matches: 1, bytes: 55, ..Default::default() })?;
+
+        let messages: Vec<Value> = String::from_utf8(out).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let types: Vec<&str> = messages.iter().map(|message| message["type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["begin", "match", "context", "end"]);
+
+        let data = &messages[1]["data"];
+        assert_eq!(data["path"]["text"], "src/a.rs");
+        assert_eq!(data["lines"]["text"], "let foo = 1;\n");
+        assert_eq!(data["line_number"], 3);
+        assert_eq!(data["absolute_offset"], 40);
+        assert_eq!(data["submatches"], json!([{"match": {"text": "foo"}, "start": 4, "end": 7}]));
+
+        let stats = &messages[3]["data"]["stats"];
+        assert_eq!(stats["bytes_searched"], 55);
+        assert_eq!(stats["matched_lines"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_file_without_lines_writes_nothing() -> io::Result<()> {
+        let mut out = Vec::new();
+        JsonFile::new("a.txt").finish(&mut out, &FileSummary::default())?;
+
+        assert!(out.is_empty());
+        Ok(())
+    }
+}
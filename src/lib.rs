@@ -0,0 +1,3757 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write, BufRead, BufReader, Read};
+use std::ops::{ControlFlow, Range};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use log::{debug, log_enabled, Level};
+use memmap2::Mmap;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::hir::literal::Extractor;
+
+use crate::command::CommandReader;
+use crate::json::{CountingWriter, JsonFile};
+use crate::multiline::LineMatches;
+use crate::tail::TailLines;
+
+pub use crate::color::{ColorChoice, Colors};
+pub use crate::config::{config_path, read_config, CONFIG_ENV_VAR};
+pub use crate::encoding::Encoding;
+pub use crate::matcher::{FixedStringMatcher, Matcher};
+pub use crate::walk::{build_glob_set, build_overrides, build_types, default_type_names, parse_size, PathGlob, SortBy, WalkOptions};
+
+mod bre;
+mod color;
+mod command;
+mod config;
+mod encoding;
+mod json;
+mod matcher;
+mod multiline;
+mod parallel;
+mod tail;
+mod walk;
+#[cfg(feature = "unicode-words")]
+mod unicode_words;
+
+/// File name that means "read standard input".
+pub const STDIN_FILE_NAME: &str = "-";
+
+/// How standard input is named in headers and messages.
+pub const STDIN_LABEL: &str = "(standard input)";
+
+/// Line written between context groups that aren't adjacent.
+pub const DEFAULT_GROUP_SEPARATOR: &str = "--";
+
+/// Default header pattern for `--show-context-line`: Rust item declarations.
+pub const DEFAULT_CONTEXT_LINE_REGEX: &str = r"^\s*(pub(\([^)]*\))?\s+)?((async|const|unsafe|extern \S+)\s+)*(fn|impl|struct|enum|trait|mod)\b";
+
+/// Compiled size allowed for the pattern, raised well above the regex crate's
+/// default so that thousands of `-f` patterns still compile.
+const PATTERN_SIZE_LIMIT: usize = 256 * (1 << 20);
+
+/// Regular expression dialect the pattern is written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PatternType {
+    /// POSIX basic syntax: `\(`, `\)`, `\{`, `\}` are operators
+    Basic,
+    /// Extended syntax, as understood by the regex crate
+    #[default]
+    Extended,
+    /// A literal string; no character is special
+    Fixed,
+    /// Perl-compatible syntax with lookaround and backreferences
+    #[cfg(feature = "pcre")]
+    Perl,
+}
+
+impl PatternType {
+    fn is_perl(self) -> bool {
+        #[cfg(feature = "pcre")]
+        if self == PatternType::Perl {
+            return true;
+        }
+
+        false
+    }
+}
+
+/// How to treat files that look binary, i.e. contain a NUL byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BinaryFiles {
+    /// Print a one-line "Binary file X matches" note instead of the lines
+    #[default]
+    Binary,
+    /// Search and print them like any other file
+    Text,
+    /// Assume they don't match
+    WithoutMatch,
+}
+
+/// Line terminator written after each line of output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputEol {
+    /// Unix line endings (\n)
+    #[default]
+    Lf,
+    /// Windows line endings (\r\n)
+    Crlf,
+    /// NUL bytes, which `--null-data` uses for its output records
+    #[value(skip)]
+    Nul,
+}
+
+impl OutputEol {
+    fn as_str(self) -> &'static str {
+        match self {
+            OutputEol::Lf => "\n",
+            OutputEol::Crlf => "\r\n",
+            OutputEol::Nul => "\0",
+        }
+    }
+
+    fn as_bytes(self) -> &'static [u8] {
+        self.as_str().as_bytes()
+    }
+}
+
+/// How file paths are written, in place of the path as given or as the
+/// walk built it from its root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PathFormat {
+    /// Relative to the directory searched, or for a file named on its own, just its name
+    Relative,
+    /// Absolute
+    Absolute,
+}
+
+/// A window of byte columns, 1-based and inclusive like `cut -b`, that the
+/// pattern is tested against. Written `START:END`; either side may be left
+/// out to run from the start or to the end of the line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColumnRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl ColumnRange {
+    /// Returns the part of `line` inside the window, widened outwards so a
+    /// multi-byte character cut by either edge is kept whole.
+    pub fn slice(self, line: &str) -> &str {
+        &line[self.range(line)]
+    }
+
+    /// Returns the byte range of `line` that `slice` covers.
+    pub fn range(self, line: &str) -> Range<usize> {
+        let mut start = (self.start - 1).min(line.len());
+        let mut end = self.end.map_or(line.len(), |end| end.min(line.len()));
+
+        while !line.is_char_boundary(start) {
+            start -= 1;
+        }
+        while !line.is_char_boundary(end) {
+            end += 1;
+        }
+
+        start..end.max(start)
+    }
+}
+
+impl FromStr for ColumnRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once(':').ok_or_else(|| format!("expected START:END, got '{}'", s))?;
+
+        let parse_column = |column: &str| -> Result<Option<usize>, String> {
+            if column.is_empty() {
+                return Ok(None);
+            }
+            match column.parse::<usize>() {
+                Ok(0) | Err(_) => Err(format!("'{}' is not a column number (columns start at 1)", column)),
+                Ok(n) => Ok(Some(n)),
+            }
+        };
+
+        let start = parse_column(start)?.unwrap_or(1);
+        let end = parse_column(end)?;
+
+        if end.is_some_and(|end| end < start) {
+            return Err(format!("end column comes before start column in '{}'", s));
+        }
+
+        Ok(ColumnRange { start, end })
+    }
+}
+
+/// Settings that control how the pattern is compiled.
+#[derive(Default)]
+pub struct RegexOptions {
+    pub insensitive: bool,
+    pub pattern_type: PatternType,
+    pub starts_with: bool,
+    pub ends_with: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    /// Let `^` and `$` match at line boundaries, for `SearchOptions::multiline`.
+    pub multiline: bool,
+}
+
+/// Settings that control how each file is searched and what gets written.
+#[derive(Default)]
+pub struct SearchOptions {
+    pub show_header: bool,
+    pub no_header: bool,
+    pub invert_match: bool,
+    pub show_line_numbers: bool,
+    pub count_matching_lines: bool,
+    #[cfg(feature = "unicode-words")]
+    pub match_whole_words: bool,
+    pub context_line_regex: Option<Regex>,
+    pub sum_matches: bool,
+    pub match_filenames: bool,
+    pub tail: bool,
+    pub output_eol: OutputEol,
+    pub columns: Option<ColumnRange>,
+    /// Leave out the count line of a file whose count is zero.
+    pub count_nonzero: bool,
+    pub count_bytes: bool,
+    /// Write each file's number of matches, counting every one on a line.
+    pub count_matches: bool,
+    /// Write each file's number of lines the context options would print,
+    /// matches and context alike, counting overlapping context once.
+    pub count_with_context: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub only_matching: bool,
+    pub files_with_matches: bool,
+    pub files_without_match: bool,
+    pub quiet: bool,
+    /// With `files_with_matches`, stop the whole search at the first file
+    /// that matches, so only its name is written.
+    pub first_only: bool,
+    /// Stop reading each file after this many selected lines.
+    pub max_count: Option<u64>,
+    /// Skip unreadable files without a message; `had_errors` is still set.
+    pub no_messages: bool,
+    /// Search the files under directory arguments instead of rejecting them.
+    pub recursive: bool,
+    pub walk: WalkOptions,
+    /// End file names with a NUL byte instead of `:` or a line terminator.
+    pub null: bool,
+    /// Read NUL-terminated records instead of lines. Callers normally pair
+    /// this with `OutputEol::Nul` so records are written back the same way.
+    pub null_data: bool,
+    /// Highlight output with these colors; `None` writes plain text.
+    pub colors: Option<Colors>,
+    pub binary_files: BinaryFiles,
+    /// Add where the first match and the first NUL byte are to the "Binary
+    /// file X matches" note, as byte offsets like `byte_offset` prints.
+    pub binary_offset: bool,
+    /// Flush `out` after every record written.
+    pub line_buffered: bool,
+    /// Name to show for standard input instead of `(standard input)`.
+    pub label: Option<String>,
+    /// Rewrite file paths this way before showing them; `None` shows them
+    /// as given.
+    pub path_format: Option<PathFormat>,
+    /// Prefix output with its byte offset in the file.
+    pub byte_offset: bool,
+    /// With `only_matching`, write the matches on each line together on one
+    /// line, separated by this, instead of one per line.
+    pub join_matches: Option<String>,
+    /// Line between non-adjacent context groups; `None` means `--`.
+    pub group_separator: Option<String>,
+    pub no_group_separator: bool,
+    /// How many files to search at once; 0 or 1 searches them one by one.
+    pub threads: usize,
+    /// Write ripgrep's JSON Lines messages instead of grep-style lines.
+    /// Callers should leave `colors` off, since the lines are written as-is.
+    pub json: bool,
+    /// Print selected lines (or with `only_matching`, matches) with each match
+    /// rewritten by this template; `$1` and `${name}` refer to capture groups.
+    pub replace: Option<String>,
+    /// Match each file as a whole so patterns can span lines; every line a
+    /// match touches is selected. Pair with `RegexOptions::multiline`.
+    pub multiline: bool,
+    /// Search compressed files (`.gz`, `.bz2`, `.xz`, `.zst`, ...) by their
+    /// decompressed contents, using the format's command-line tool.
+    pub search_zip: bool,
+    /// Search what this command prints for each file, rather than the file
+    /// itself. It's run with the file's path as its argument and the file
+    /// on its standard input. Takes precedence over `search_zip`.
+    pub pre: Option<String>,
+    /// Transcode input from this encoding to UTF-8 before matching. Byte
+    /// offsets then count bytes of the UTF-8 text. Without one, a file that
+    /// starts with a UTF-8 or UTF-16 byte order mark is read as the mark says.
+    pub encoding: Option<Encoding>,
+    /// Memory-map files and search the mapped bytes instead of reading them
+    /// through a buffer. Only regular files are mapped; anything else, or a
+    /// file the platform can't map, is read as usual.
+    pub mmap: bool,
+    /// Also map files of at least this many bytes, reading smaller ones
+    /// through a buffer where mapping them wouldn't pay off.
+    pub mmap_threshold: Option<u64>,
+    /// Write a `line:column:` prefixed copy of each selected line for every
+    /// match on it, for Vim's quickfix list and similar error parsers.
+    pub vimgrep: bool,
+    /// With `show_header`, print each file name once above its lines instead
+    /// of on every line, leaving a blank line between files.
+    pub heading: bool,
+    /// Print the 1-based byte column of the first match after the line
+    /// number, or of each match with `only_matching`.
+    pub column: bool,
+    /// Write every line, the unselected ones as context, so matches are
+    /// only highlighted rather than filtered.
+    pub passthru: bool,
+}
+
+/// Totals for a single searched file.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FileSummary {
+    pub lines: u64,
+    pub matching_lines: u64,
+    /// Lines picked for output: matching lines, or non-matching ones with `invert_match`.
+    pub selected_lines: u64,
+    pub matches: u64,
+    /// Bytes read from the file.
+    pub bytes: u64,
+}
+
+/// Totals for a whole search across every file.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SearchSummary {
+    pub files_searched: u64,
+    pub files_matched: u64,
+    pub total_matches: u64,
+    pub total_lines: u64,
+    pub total_selected_lines: u64,
+    pub total_bytes: u64,
+    pub had_errors: bool,
+}
+
+impl SearchSummary {
+    /// Renders the summary as a single-line JSON object, with the wall time
+    /// the caller measured for the run.
+    pub fn to_json(&self, elapsed_ms: u128) -> String {
+        format!(
+            "{{\"files_searched\":{},\"files_matched\":{},\"total_matches\":{},\"total_lines\":{},\"had_errors\":{},\"elapsed_ms\":{}}}",
+            self.files_searched,
+            self.files_matched,
+            self.total_matches,
+            self.total_lines,
+            self.had_errors,
+            elapsed_ms,
+        )
+    }
+
+    /// Renders the summary as the human-readable `--stats` block, with the
+    /// wall time the caller measured for the run and lines ended by `eol`.
+    pub fn to_stats(&self, elapsed: Duration, eol: OutputEol) -> String {
+        format!(
+            "{eol}{} matches{eol}{} matched lines{eol}{} files contained matches{eol}{} files searched{eol}{} bytes searched{eol}{:.6} seconds{eol}",
+            self.total_matches,
+            self.total_selected_lines,
+            self.files_matched,
+            self.files_searched,
+            self.total_bytes,
+            elapsed.as_secs_f64(),
+            eol = eol.as_str(),
+        )
+    }
+
+    fn add(&mut self, file_summary: &FileSummary) {
+        self.files_searched += 1;
+        if file_summary.selected_lines > 0 {
+            self.files_matched += 1;
+        }
+        self.total_matches += file_summary.matches;
+        self.total_lines += file_summary.lines;
+        self.total_selected_lines += file_summary.selected_lines;
+        self.total_bytes += file_summary.bytes;
+    }
+}
+
+/// Searches each file in turn, writing results to `out`, and returns totals
+/// for the whole run. With `recursive`, directories are walked and every file
+/// under them searched. With more than one of `threads`, several files are
+/// searched at once, but output still comes out file by file in order. A file
+/// that can't be read is reported on stderr and skipped; the summary's
+/// `had_errors` records that it happened. If `out` is a pipe that gets
+/// closed, the search stops there and returns what it had found so far.
+pub fn search<P: AsRef<Path>, W: Write>(
+    file_names: &[P],
+    matcher: &dyn Matcher,
+    options: &SearchOptions,
+    out: W,
+) -> io::Result<SearchSummary> {
+    let start = Instant::now();
+    let mut out = CountingWriter::new(out);
+    let mut summary = SearchSummary::default();
+    let roots: Vec<&Path> = file_names.iter().map(|file_name| file_name.as_ref()).collect();
+
+    // Following a file never finishes, so it can't wait its turn behind a
+    // worker, and workers would open files past the one `first_only` wants.
+    let searched = if options.threads > 1 && !options.tail && !options.first_only && (roots.len() > 1 || options.recursive) {
+        debug!("searching on {} threads", options.threads);
+        parallel::search(&roots, matcher, options, &mut out, &mut summary)
+    }
+    else {
+        debug!("searching on one thread");
+        let mut wrote_lines = false;
+        for_each_target(&roots, options, |target| match target {
+            Target::File(path, root) => {
+                let written = out.count;
+                let result = search_file(&path, root, matcher, options, &mut out, wrote_lines);
+                wrote_lines |= out.count > written;
+                record_file(&path, root, result, options, &mut summary)
+            }
+            Target::Unusable(path, root, err) => {
+                report_error(options, &mut summary, &path, root, err);
+                Ok(ControlFlow::Continue(()))
+            }
+        })
+    };
+
+    match searched {
+        // Whoever was reading has stopped (`| head`), so there's no one to tell.
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(summary),
+        searched => searched?,
+    }
+
+    if options.sum_matches {
+        write_line(&mut out, options.output_eol, format_args!("{}", summary.total_matches))?;
+    }
+
+    if options.json {
+        let bytes_printed = out.count;
+        json::write_summary(&mut out, &summary, bytes_printed, start.elapsed())?;
+    }
+
+    Ok(summary)
+}
+
+/// Writes the path of every file `search` would read, one per line (or
+/// NUL-terminated with `null`), without reading any of them. Each listed file
+/// counts as both searched and matched in the summary, so the exit status
+/// tells whether anything was listed. Like `search`, it stops quietly if
+/// `out` is a closed pipe.
+pub fn list_files<P: AsRef<Path>, W: Write>(file_names: &[P], options: &SearchOptions, mut out: W) -> io::Result<SearchSummary> {
+    let mut summary = SearchSummary::default();
+    let roots: Vec<&Path> = file_names.iter().map(|file_name| file_name.as_ref()).collect();
+
+    let listed = for_each_target(&roots, options, |target| {
+        match target {
+            Target::File(path, root) => {
+                summary.files_searched += 1;
+                summary.files_matched += 1;
+                write_file_name(&mut out, &file_label(&path, root, options), options)?;
+            }
+            Target::Unusable(path, root, err) => report_error(options, &mut summary, &path, root, err),
+        }
+        Ok(ControlFlow::Continue(()))
+    });
+
+    match listed {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(summary),
+        listed => listed.map(|()| summary),
+    }
+}
+
+/// One thing `search` has to deal with: a file to read, or a named path or
+/// walk entry that can't be searched, with the reason why. Each comes with
+/// the path named on the command line that it was found under.
+enum Target<'a> {
+    File(Cow<'a, Path>, &'a Path),
+    Unusable(Cow<'a, Path>, &'a Path, String),
+}
+
+/// Calls `visit` with everything `search` covers, in order: each named file,
+/// or with `recursive` each file under a named directory. Stops early when
+/// `visit` breaks or fails.
+fn for_each_target<'a, F>(roots: &[&'a Path], options: &SearchOptions, mut visit: F) -> io::Result<()>
+where
+    F: FnMut(Target<'a>) -> io::Result<ControlFlow<()>>,
+{
+    for &root in roots {
+        let flow = if !root.is_dir() {
+            visit(Target::File(Cow::Borrowed(root), root))?
+        }
+        else if !options.recursive {
+            visit(Target::Unusable(Cow::Borrowed(root), root, String::from("Is a directory")))?
+        }
+        else {
+            let mut flow = ControlFlow::Continue(());
+            for entry in walk::walk(root, &options.walk) {
+                flow = match entry {
+                    Ok(path) => visit(Target::File(Cow::Owned(path), root))?,
+                    // A directory that can't be read is reported by its own path.
+                    Err(err) => match walk::error_path(&err) {
+                        Some((path, cause)) => visit(Target::Unusable(Cow::Owned(path.to_path_buf()), root, cause))?,
+                        None => visit(Target::Unusable(Cow::Borrowed(root), root, err.to_string()))?,
+                    },
+                };
+
+                if flow.is_break() {
+                    break;
+                }
+            }
+            flow
+        };
+
+        if flow.is_break() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Folds the result of searching one file into `summary`. Breaks once `-q`,
+/// or `-l` with `first_only`, has seen a match; a broken pipe ends the whole
+/// search.
+///
+/// A file whose output hit the broken pipe had something to print, which
+/// short of `files_without_match` means it had a selected line, so it counts
+/// as matched.
+fn record_file(
+    path: &Path,
+    root: &Path,
+    result: io::Result<FileSummary>,
+    options: &SearchOptions,
+    summary: &mut SearchSummary,
+) -> io::Result<ControlFlow<()>> {
+    match result {
+        Ok(file_summary) => {
+            summary.add(&file_summary);
+
+            if (options.quiet || (options.first_only && options.files_with_matches)) && file_summary.selected_lines > 0 {
+                // Nothing more can change the outcome, so stop at the first match.
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+            summary.files_searched += 1;
+            if !options.files_without_match {
+                summary.files_matched += 1;
+            }
+            return Err(err);
+        }
+        Err(err) => report_error(options, summary, path, root, err),
+    }
+
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Notes a file that couldn't be searched, telling the user unless `-s` is on.
+fn report_error<E: fmt::Display>(options: &SearchOptions, summary: &mut SearchSummary, path: &Path, root: &Path, err: E) {
+    if !options.no_messages {
+        eprintln!("rusty_grep: {}: {}", file_label(path, root, options), err);
+    }
+    summary.had_errors = true;
+}
+
+pub fn build_regex(regex_str: &str, options: &RegexOptions) -> Result<Regex, regex::Error> {
+    build_alternation(&[regex_str], options)
+}
+
+/// Compiles `patterns` into one regex that matches wherever any of them does.
+/// Each pattern is translated from its dialect on its own; the anchoring
+/// options then apply to the alternation as a whole.
+fn build_alternation<S: AsRef<str>>(patterns: &[S], options: &RegexOptions) -> Result<Regex, regex::Error> {
+    let pattern = alternation_pattern(patterns, options);
+    debug!("compiled pattern: {}", pattern);
+    // Extracting literals from thousands of `-f` patterns isn't free.
+    if log_enabled!(Level::Debug) {
+        debug!("literal prefilter: {}", describe_prefilter(&pattern, options));
+    }
+
+    RegexBuilder::new(&pattern)
+        .case_insensitive(options.insensitive)
+        .multi_line(options.multiline)
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .dfa_size_limit(PATTERN_SIZE_LIMIT)
+        .build()
+}
+
+/// Lists the literals a match has to start with, which the regex engine
+/// scans for before running the full pattern, or `none` when any position
+/// could start one.
+fn describe_prefilter(pattern: &str, options: &RegexOptions) -> String {
+    let hir = regex_syntax::ParserBuilder::new()
+        .case_insensitive(options.insensitive)
+        .multi_line(options.multiline)
+        .build()
+        .parse(pattern);
+    let hir = match hir {
+        Ok(hir) => hir,
+        Err(err) => return format!("none ({})", err),
+    };
+
+    let prefixes = Extractor::new().extract(&hir);
+    match prefixes.literals() {
+        Some(literals) if !literals.is_empty() && !literals.iter().any(|literal| literal.is_empty()) => literals
+            .iter()
+            .map(|literal| format!("{:?}", String::from_utf8_lossy(literal.as_bytes())))
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => String::from("none"),
+    }
+}
+
+/// Writes out the combined pattern source that `build_alternation` compiles,
+/// shared with the Perl-compatible engine.
+fn alternation_pattern<S: AsRef<str>>(patterns: &[S], options: &RegexOptions) -> String {
+    let translated: Vec<String> = patterns
+        .iter()
+        .map(|pattern| match options.pattern_type {
+            PatternType::Basic => bre::translate(pattern.as_ref()),
+            PatternType::Extended => pattern.as_ref().to_string(),
+            PatternType::Fixed => regex::escape(pattern.as_ref()),
+            #[cfg(feature = "pcre")]
+            PatternType::Perl => pattern.as_ref().to_string(),
+        })
+        .collect();
+
+    let mut pattern = if translated.is_empty() {
+        // No patterns at all (an empty `-f` file) matches nothing, as in grep.
+        String::from(r"\b\B")
+    }
+    else if translated.len() == 1 {
+        translated.into_iter().next().unwrap()
+    }
+    else {
+        translated.iter().map(|p| format!("(?:{})", p)).collect::<Vec<_>>().join("|")
+    };
+
+    if options.line_regexp {
+        pattern = format!("^(?:{})$", pattern);
+    }
+    else if options.word_regexp {
+        // Half boundaries only look outward, so a pattern that starts or ends with a
+        // non-word character (`@foo`, `foo\(\)`) still matches, unlike a bare `\b`.
+        // The Perl engine lacks them but can say the same with lookaround.
+        pattern = if options.pattern_type.is_perl() {
+            format!(r"(?<!\w)(?:{})(?!\w)", pattern)
+        }
+        else {
+            format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern)
+        };
+    }
+
+    if options.starts_with {
+        pattern = format!("^(?:{})", pattern);
+    }
+
+    if options.ends_with {
+        pattern = format!("(?:{})$", pattern);
+    }
+
+    pattern
+}
+
+/// Whether `pattern` spells out an uppercase letter, which `--smart-case`
+/// takes to mean the search should be case-sensitive. Escapes (`\S`,
+/// `\p{Lu}`, `\x4F`) and POSIX classes (`[:upper:]`) name characters rather
+/// than spell them, so they don't count. Fixed strings are all literal.
+pub fn has_uppercase_literal(pattern: &str, pattern_type: PatternType) -> bool {
+    if pattern_type == PatternType::Fixed {
+        return pattern.chars().any(char::is_uppercase);
+    }
+
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('p' | 'P' | 'x' | 'u' | 'U' | 'N') if chars.peek() == Some(&'{') => {
+                    chars.by_ref().find(|&c| c == '}');
+                }
+                Some('x') => {
+                    chars.next();
+                    chars.next();
+                }
+                Some('p' | 'P') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            '[' if chars.peek() == Some(&':') => {
+                chars.by_ref().find(|&c| c == ']');
+            }
+            c if c.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// Reads one pattern per line from `path` (`-` for standard input), as `-f` does.
+pub fn read_patterns<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+    open_reader(path)?.lines().collect()
+}
+
+/// Reads NUL-separated patterns from `path`, as `-f` does with
+/// `--patterns-null`, so a pattern can itself contain a newline.
+pub fn read_null_patterns<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+    open_reader(path)?
+        .split(b'\0')
+        .map(|pattern| String::from_utf8(pattern?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)))
+        .collect()
+}
+
+/// Builds the matcher for `patterns`, which a line matches if any of them
+/// does. A single non-empty fixed string that needs no anchoring or case
+/// folding gets the literal `memchr` matcher; everything else is compiled as
+/// a regex.
+pub fn build_matcher<S: AsRef<str>>(patterns: &[S], options: &RegexOptions) -> Result<Box<dyn Matcher>, PatternError> {
+    let plain_literal = patterns.len() == 1
+        && !patterns[0].as_ref().is_empty()
+        && options.pattern_type == PatternType::Fixed
+        && !options.insensitive
+        && !options.starts_with
+        && !options.ends_with
+        && !options.word_regexp
+        && !options.line_regexp;
+
+    if plain_literal {
+        debug!("searching for the literal {:?} with memchr", patterns[0].as_ref());
+        Ok(Box::new(FixedStringMatcher::new(patterns[0].as_ref())))
+    }
+    else if options.pattern_type.is_perl() {
+        build_perl_matcher(patterns, options)
+    }
+    else {
+        Ok(Box::new(build_alternation(patterns, options)?))
+    }
+}
+
+/// Why the patterns given couldn't be compiled, from whichever engine was asked.
+#[derive(Debug)]
+pub enum PatternError {
+    Regex(regex::Error),
+    #[cfg(feature = "pcre")]
+    Perl(fancy_regex::Error),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::Regex(err) => err.fmt(f),
+            #[cfg(feature = "pcre")]
+            PatternError::Perl(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+impl From<regex::Error> for PatternError {
+    fn from(err: regex::Error) -> Self {
+        PatternError::Regex(err)
+    }
+}
+
+#[cfg(feature = "pcre")]
+impl From<fancy_regex::Error> for PatternError {
+    fn from(err: fancy_regex::Error) -> Self {
+        PatternError::Perl(err)
+    }
+}
+
+#[cfg(feature = "pcre")]
+fn build_perl_matcher<S: AsRef<str>>(patterns: &[S], options: &RegexOptions) -> Result<Box<dyn Matcher>, PatternError> {
+    let pattern = alternation_pattern(patterns, options);
+    debug!("compiled pattern (fancy-regex): {}", pattern);
+    let regex = fancy_regex::RegexBuilder::new(&pattern)
+        .case_insensitive(options.insensitive)
+        .multi_line(options.multiline)
+        .build()?;
+
+    Ok(Box::new(regex))
+}
+
+#[cfg(not(feature = "pcre"))]
+fn build_perl_matcher<S: AsRef<str>>(_patterns: &[S], _options: &RegexOptions) -> Result<Box<dyn Matcher>, PatternError> {
+    unreachable!("the Perl pattern type only exists with the pcre feature")
+}
+
+/// A selected line handed to the `search_with_callback` callback.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MatchResult<'a> {
+    pub line_number: u64,
+    pub line: &'a str,
+    /// Byte ranges of each match within `line`; empty for lines selected by `invert_match`.
+    pub matches: Vec<Range<usize>>,
+}
+
+/// What `search_with_callback` has to say about the input besides its
+/// matches: the things the CLI would print a note for, or stop early on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The input looks binary and has a selected line. No lines are handed
+    /// over and the search stops, as it does for "Binary file X matches".
+    /// `offset` is where the first match is and `nul_offset` where the first
+    /// NUL byte is, in bytes from the start.
+    BinaryMatches { offset: u64, nul_offset: u64 },
+    /// The input looks binary and `binary_files` says to skip it.
+    BinarySkipped,
+    /// `max_count` lines have been selected, so the rest of the input isn't read.
+    MaxCountReached,
+}
+
+/// Searches `reader` and calls `on_match` for every selected line instead of
+/// writing anything, so embedders can render results however they like.
+/// Lines are selected exactly as `search` selects them; output formatting
+/// options are ignored. Anything `search` would print a note about goes to
+/// `on_diagnostic`.
+pub fn search_with_callback<R, F, D>(
+    reader: R,
+    matcher: &dyn Matcher,
+    options: &SearchOptions,
+    mut on_match: F,
+    mut on_diagnostic: D,
+) -> io::Result<FileSummary>
+where
+    R: BufRead,
+    F: FnMut(&MatchResult),
+    D: FnMut(&Diagnostic),
+{
+    let mut text = Text::Reader(decode_input(Box::new(reader), options)?);
+    let first_nul = binary_start(&mut text, options)?;
+    if first_nul.is_some() && options.binary_files == BinaryFiles::WithoutMatch {
+        on_diagnostic(&Diagnostic::BinarySkipped);
+    }
+
+    let (records, line_matches) = prepare_records(text, first_nul.is_some(), matcher, options)?;
+    let mut tally = Tally::default();
+
+    scan_records(records, &line_matches, matcher, options, &mut tally, |record| {
+        if !record.selected {
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        let matches = if record.is_match { match_spans(record.matcher, &record.line, options) } else { Vec::new() };
+
+        if let Some(nul) = first_nul {
+            let offset = record.position.byte_offset + matches.first().map_or(0, |span| span.start) as u64;
+            on_diagnostic(&Diagnostic::BinaryMatches { offset, nul_offset: nul as u64 });
+            return Ok(ControlFlow::Break(()));
+        }
+
+        on_match(&MatchResult { line_number: u64::from(record.position.line_number), line: &record.line, matches });
+
+        if record.max_count_reached {
+            on_diagnostic(&Diagnostic::MaxCountReached);
+            return Ok(ControlFlow::Break(()));
+        }
+        Ok(ControlFlow::Continue(()))
+    })?;
+
+    Ok(tally.summary)
+}
+
+/// Returns totals for the file on success, writes matches to `out`.
+pub fn process_file_name<P: AsRef<Path>, W: Write>(
+    file_name: P,
+    matcher: &dyn Matcher,
+    options: &SearchOptions,
+    out: W,
+) -> io::Result<FileSummary> {
+    let file_name = file_name.as_ref();
+    search_file(file_name, file_name, matcher, options, out, false)
+}
+
+/// `process_file_name` for a file found under `root`, with `after_lines`
+/// telling a `heading` search that an earlier file has written lines, so this
+/// one's need a blank line first.
+fn search_file<P: AsRef<Path>, W: Write>(
+    file_name: P,
+    root: &Path,
+    matcher: &dyn Matcher,
+    options: &SearchOptions,
+    mut out: W,
+    after_lines: bool,
+) -> io::Result<FileSummary> {
+    let file_path = file_name.as_ref();
+    let file_name_str = file_label(file_path, root, options);
+    let file_name_str = file_name_str.as_ref();
+
+    if options.match_filenames && !options.sum_matches && !is_stdin(file_path) && matcher.is_match(file_name_str) {
+        // Kept distinct from `file:line` output so the two kinds of hit can't be confused.
+        write_line(&mut out, options.output_eol, format_args!("File name {} matches", file_name_str))?;
+    }
+
+    let map;
+    let mut text = match open_input(file_path, options)? {
+        Input::Mapped(mapped) => {
+            map = mapped;
+            decode_bytes(&map, options)
+        }
+        Input::Stream(reader) => Text::Reader(decode_input(reader, options)?),
+    };
+    let first_nul = binary_start(&mut text, options)?;
+    if first_nul.is_some() && options.binary_files == BinaryFiles::WithoutMatch {
+        debug!("skipping {}: binary file", file_path.display());
+    }
+
+    let (records, line_matches) = prepare_records(text, first_nul.is_some(), matcher, options)?;
+    let totals_only = totals_only(options);
+    let mut tally = Tally::default();
+    let mut has_selected_line = false;
+    let mut pending_context_line: Option<(Position, Cow<str>)> = None;
+    let mut before: VecDeque<(Position, Cow<str>)> = VecDeque::with_capacity(options.before_context);
+    let mut after_remaining: usize = 0;
+    let mut output = LineOutput {
+        file_name: file_name_str,
+        matcher,
+        last_written: None,
+        json: options.json.then(|| JsonFile::new(file_name_str)),
+        heading_pending: uses_heading(options),
+        after_lines,
+        counted: 0,
+    };
+
+    scan_records(records, &line_matches, matcher, options, &mut tally, |record| {
+        let Record { line, position, matcher, is_match, selected, past_max_count, max_count_reached } = record;
+        // Past the limit only the trailing context of the last selected line is printed.
+        let done = |after_remaining: usize| max_count_reached && (after_remaining == 0 || totals_only);
+
+        if past_max_count {
+            emit_line(&mut out, &mut output, options, position, &line, '-')?;
+            after_remaining -= 1;
+            return Ok(if done(after_remaining) { ControlFlow::Break(()) } else { ControlFlow::Continue(()) });
+        }
+
+        output.matcher = matcher;
+
+        if selected {
+            if options.quiet {
+                return Ok(ControlFlow::Break(()));
+            }
+
+            if options.files_with_matches {
+                // One selected line is enough to name the file; don't read the rest.
+                write_file_name(&mut out, file_name_str, options)?;
+                return Ok(ControlFlow::Break(()));
+            }
+
+            if options.files_without_match {
+                has_selected_line = true;
+                return Ok(ControlFlow::Break(()));
+            }
+
+            if let Some(nul) = first_nul
+                && !totals_only
+            {
+                // Printing lines of a binary file would dump garbage on the terminal.
+                if !options.json {
+                    let offsets = if options.binary_offset {
+                        let match_start = match_spans(matcher, &line, options).first().map_or(0, |span| span.start);
+                        format!(" at byte {} (NUL byte at {})", position.byte_offset + match_start as u64, nul)
+                    }
+                    else {
+                        String::new()
+                    };
+                    write_line(&mut out, options.output_eol, format_args!("Binary file {} matches{}", file_name_str, offsets))?;
+                }
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+
+        if options.context_line_regex.as_ref().is_some_and(|r| r.is_match(&line)) {
+            pending_context_line = Some((position, line.clone()));
+        }
+
+        if should_write_line(is_match, options.invert_match, totals_only) {
+            let first_line_number = before.front().map_or(position.line_number, |(p, _)| p.line_number);
+
+            // Each context line is printed once, ahead of the first group written after it,
+            // unless that group is about to print it anyway.
+            if let Some((context_position, context_text)) = pending_context_line.take()
+                && context_position.line_number < first_line_number
+                && output.last_written.is_none_or(|last| context_position.line_number > last)
+            {
+                emit_line(&mut out, &mut output, options, context_position, &context_text, '=')?;
+            }
+
+            for (before_position, before_text) in before.drain(..) {
+                emit_line(&mut out, &mut output, options, before_position, &before_text, '-')?;
+            }
+
+            if options.only_matching {
+                let searched = options.columns.map_or(line.as_ref(), |columns| columns.slice(&line));
+                // `-b` gives the offset of each match rather than of its line.
+                let searched_start = options.columns.map_or(0, |columns| columns.range(&line).start);
+                let spans: Box<dyn Iterator<Item = (Range<usize>, Cow<str>)>> = match &options.replace {
+                    Some(template) => Box::new(matcher.replacements(searched, template).map(|(span, text)| (span, Cow::Owned(text)))),
+                    None => Box::new(matcher.find_iter(searched).map(|span| (span.clone(), Cow::Borrowed(&searched[span])))),
+                };
+
+                let match_position = |span: &Range<usize>| Position {
+                    byte_offset: position.byte_offset + (searched_start + span.start) as u64,
+                    column: (options.vimgrep || options.column).then_some(searched_start + span.start + 1),
+                    ..position
+                };
+                let spans = spans.filter(|(span, _)| !span.is_empty()).map(|(span, text)| {
+                    let text = paint_part(options, |colors| &colors.matched, &text).into_owned();
+                    (span, text)
+                });
+
+                if let Some(separator) = &options.join_matches {
+                    // One line for all of them, placed where the first one is.
+                    let (spans, texts): (Vec<_>, Vec<_>) = spans.unzip();
+                    if let Some(first) = spans.first() {
+                        emit_line(&mut out, &mut output, options, match_position(first), &texts.join(separator), ':')?;
+                    }
+                }
+                else {
+                    for (span, text) in spans {
+                        emit_line(&mut out, &mut output, options, match_position(&span), &text, ':')?;
+                    }
+                }
+            }
+            else {
+                let text = match &options.replace {
+                    Some(template) => replace_matches(matcher, &line, template, options),
+                    None => highlight_matches(matcher, &line, options),
+                };
+
+                if options.vimgrep {
+                    // One entry per match; a line selected by `-v` has none, so it gets one at column 1.
+                    let mut starts: Vec<usize> = if is_match { match_spans(matcher, &line, options).iter().map(|span| span.start).collect() } else { Vec::new() };
+                    if starts.is_empty() {
+                        starts.push(0);
+                    }
+
+                    for start in starts {
+                        let match_position = Position { column: Some(start + 1), ..position };
+                        emit_line(&mut out, &mut output, options, match_position, &text, ':')?;
+                    }
+                }
+                else {
+                    // A line selected by `-v` has no match, so it counts as starting at column 1.
+                    let column = options.column.then(|| {
+                        let first = if is_match { match_spans(matcher, &line, options).first().map(|span| span.start) } else { None };
+                        first.unwrap_or(0) + 1
+                    });
+                    emit_line(&mut out, &mut output, options, Position { column, ..position }, &text, ':')?;
+                }
+                after_remaining = options.after_context;
+            }
+
+            if options.tail {
+                out.flush()?;
+            }
+        }
+        else if options.passthru && !totals_only {
+            emit_line(&mut out, &mut output, options, position, &line, '-')?;
+            if options.tail {
+                out.flush()?;
+            }
+        }
+        else if after_remaining > 0 && !totals_only {
+            emit_line(&mut out, &mut output, options, position, &line, '-')?;
+            after_remaining -= 1;
+        }
+        else if options.before_context > 0 && !totals_only && !options.only_matching {
+            if before.len() == options.before_context {
+                before.pop_front();
+            }
+            before.push_back((position, line));
+        }
+
+        Ok(if done(after_remaining) { ControlFlow::Break(()) } else { ControlFlow::Continue(()) })
+    })?;
+
+    if options.files_without_match && !has_selected_line && !options.quiet {
+        write_file_name(&mut out, file_name_str, options)?;
+    }
+
+    let summary = tally.summary;
+    let writes_counts = !options.sum_matches && !options.files_with_matches && !options.files_without_match && !options.quiet;
+    let counts = [
+        (options.count_matching_lines, summary.selected_lines),
+        (options.count_bytes, tally.matched_bytes),
+        (options.count_matches, summary.matches),
+        (options.count_with_context, output.counted),
+    ];
+
+    for (wanted, count) in counts {
+        if wanted && writes_counts && !(options.count_nonzero && count == 0) {
+            write_count(&mut out, file_name_str, count, options)?;
+        }
+    }
+
+    if let Some(json) = output.json {
+        json.finish(&mut out, &summary)?;
+    }
+
+    Ok(summary)
+}
+
+/// Where the first NUL byte is in a file that looks binary, unless
+/// `binary_files` or `null_data` say to treat every file as text.
+fn binary_start(text: &mut Text, options: &SearchOptions) -> io::Result<Option<usize>> {
+    if options.binary_files == BinaryFiles::Text || options.null_data {
+        return Ok(None);
+    }
+
+    text.find_nul()
+}
+
+/// The records of `text` to select from, and with `multiline` what the
+/// whole-text matches cover on each of them, in order. A `binary` file that
+/// `binary_files` says to skip has none, so it reports (and counts) as
+/// having no matches.
+fn prepare_records<'a>(text: Text<'a>, binary: bool, matcher: &dyn Matcher, options: &SearchOptions) -> io::Result<(Records<'a>, Vec<LineMatches>)> {
+    if binary && options.binary_files == BinaryFiles::WithoutMatch {
+        return Ok((Box::new(std::iter::empty()), Vec::new()));
+    }
+
+    if options.multiline && !options.tail {
+        let terminator = if options.null_data { b'\0' } else { b'\n' };
+        let (records, line_matches) = multiline::split_matches(&text.read_all()?, matcher, terminator);
+        return Ok((Box::new(records.into_iter().map(|(line, len)| Ok((Cow::Owned(line), len)))), line_matches));
+    }
+
+    Ok((text.records(options), Vec::new()))
+}
+
+/// One record as `scan_records` hands it over.
+struct Record<'a, 'm> {
+    line: Cow<'a, str>,
+    position: Position,
+    /// What to match the line with: with `multiline`, only its share of the
+    /// whole-text matches.
+    matcher: &'m dyn Matcher,
+    is_match: bool,
+    /// Whether the line matches, or with `invert_match` doesn't.
+    selected: bool,
+    /// Set once `max_count` lines have been selected before this one. Such
+    /// lines are neither matched nor selected, and only make trailing context.
+    past_max_count: bool,
+    /// Set once `max_count` lines have been selected, this one included.
+    max_count_reached: bool,
+}
+
+/// Totals `scan_records` keeps as it goes.
+#[derive(Default)]
+struct Tally {
+    summary: FileSummary,
+    /// Bytes the matches cover, counted only for `count_bytes`.
+    matched_bytes: u64,
+}
+
+/// Matches each of `records` and decides whether it's selected, then hands
+/// it to `visit`, which says whether to go on. This is the one place lines
+/// are selected, so the CLI's output and `search_with_callback` always agree.
+/// Nothing more is read once `visit` breaks, so a caller that stops at
+/// `max_count` doesn't read past it.
+fn scan_records<'a, 'm, F>(
+    records: Records<'a>,
+    line_matches: &'m [LineMatches],
+    matcher: &'m dyn Matcher,
+    options: &SearchOptions,
+    tally: &mut Tally,
+    mut visit: F,
+) -> io::Result<()>
+where
+    F: FnMut(Record<'a, 'm>) -> io::Result<ControlFlow<()>>,
+{
+    if options.max_count == Some(0) {
+        return Ok(());
+    }
+
+    let mut line_number: u32 = 0;
+    let mut max_count_reached = false;
+
+    for record in records {
+        let (line, record_len) = record?;
+        line_number += 1;
+        let position = Position { line_number, byte_offset: tally.summary.bytes, column: None };
+        tally.summary.lines = u64::from(line_number);
+        tally.summary.bytes += record_len;
+
+        let past_max_count = max_count_reached;
+        // Multiline matches were found up front; each line sees only its share.
+        let matcher: &dyn Matcher = match line_matches.get(line_number as usize - 1) {
+            Some(line_matches) => line_matches,
+            None => matcher,
+        };
+        let mut is_match = false;
+        let mut selected = false;
+
+        if !past_max_count {
+            let searched = options.columns.map_or(line.as_ref(), |columns| columns.slice(&line));
+            is_match = is_line_match(matcher, searched, options);
+
+            if is_match {
+                tally.summary.matching_lines += 1;
+
+                if !options.invert_match {
+                    tally.summary.matches += count_matches(matcher, searched);
+
+                    if options.count_bytes {
+                        tally.matched_bytes += count_matched_bytes(matcher, searched);
+                    }
+                }
+            }
+
+            selected = is_match != options.invert_match;
+            if selected {
+                tally.summary.selected_lines += 1;
+                max_count_reached = options.max_count.is_some_and(|max| tally.summary.selected_lines >= max);
+            }
+        }
+
+        let record = Record { line, position, matcher, is_match, selected, past_max_count, max_count_reached };
+        if visit(record)?.is_break() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Where a written line (or, with `-o`, a match) sits in its file.
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    line_number: u32,
+    /// Bytes from the start of the file, as `-b` prints it.
+    byte_offset: u64,
+    /// 1-based byte column of a match on the line, printed after the line
+    /// number when set.
+    column: Option<usize>,
+}
+
+/// What `emit_line` needs to know about the file being written.
+struct LineOutput<'a> {
+    file_name: &'a str,
+    matcher: &'a dyn Matcher,
+    /// Number of the last line written, to tell when a new group starts.
+    last_written: Option<u32>,
+    /// Set when writing `--json` messages instead of lines.
+    json: Option<JsonFile<'a>>,
+    /// Whether the file name still has to be written above the first line.
+    heading_pending: bool,
+    /// Whether an earlier file has written lines, which the heading has to be
+    /// kept apart from.
+    after_lines: bool,
+    /// Lines counted for `count_with_context` rather than written.
+    counted: u64,
+}
+
+/// Writes one selected (`:`), context (`-`) or enclosing (`=`) line, with a
+/// group separator first when context is on and this line doesn't follow on
+/// from the last one written.
+fn emit_line<W: Write>(
+    out: &mut W,
+    output: &mut LineOutput,
+    options: &SearchOptions,
+    position: Position,
+    text: &str,
+    separator: char,
+) -> io::Result<()> {
+    if options.count_with_context {
+        output.last_written = Some(position.line_number);
+        output.counted += 1;
+        return Ok(());
+    }
+
+    if let Some(json) = &mut output.json {
+        output.last_written = Some(position.line_number);
+        let lines = format!("{}{}", text, if options.null_data { '\0' } else { '\n' });
+
+        return if separator == ':' {
+            let submatches = if options.invert_match { Vec::new() } else { match_spans(output.matcher, text, options) };
+            json.write_line(out, "match", &lines, position.line_number, position.byte_offset, &submatches)
+        }
+        else {
+            json.write_line(out, "context", &lines, position.line_number, position.byte_offset, &[])
+        };
+    }
+
+    if output.heading_pending {
+        output.heading_pending = false;
+        if output.after_lines {
+            write_line(out, options.output_eol, format_args!(""))?;
+        }
+        write_line(out, options.output_eol, format_args!("{}", paint_part(options, |colors| &colors.file_name, output.file_name)))?;
+    }
+
+    let has_context = options.before_context > 0 || options.after_context > 0;
+    if has_context && !options.no_group_separator && output.last_written.is_some_and(|last| position.line_number > last + 1) {
+        let group_separator = options.group_separator.as_deref().unwrap_or(DEFAULT_GROUP_SEPARATOR);
+        write_line(out, options.output_eol, format_args!("{}", paint_part(options, |colors| &colors.separator, group_separator)))?;
+    }
+
+    let prefix = build_prefix(output.file_name, options, position, separator);
+    write_line(out, options.output_eol, format_args!("{}{}", prefix, text))?;
+    output.last_written = Some(position.line_number);
+
+    end_record(out, options)
+}
+
+/// Byte ranges of the non-empty matches in `line`, looking only at the
+/// `--columns` slice when there is one.
+fn match_spans(matcher: &dyn Matcher, line: &str, options: &SearchOptions) -> Vec<Range<usize>> {
+    let window = options.columns.map_or(0..line.len(), |columns| columns.range(line));
+
+    matcher
+        .find_iter(&line[window.clone()])
+        .filter(|m| !m.is_empty())
+        .map(|m| m.start + window.start..m.end + window.start)
+        .collect()
+}
+
+/// Counts the non-empty matches in `line`, as `-o` would print them.
+fn count_matches(matcher: &dyn Matcher, line: &str) -> u64 {
+    matcher.find_iter(line).filter(|m| !m.is_empty()).count() as u64
+}
+
+/// Sums the byte lengths of the non-empty matches in `line`.
+fn count_matched_bytes(matcher: &dyn Matcher, line: &str) -> u64 {
+    matcher.find_iter(line).map(|m| m.len() as u64).sum()
+}
+
+/// Writes a per-file count as `file_name:count`, or just `count` without a
+/// header. With `null` the `:` becomes a NUL, as in grep's `-c -Z`, and the
+/// count still ends with the line terminator.
+fn write_count<W: Write>(mut out: W, file_name: &str, count: u64, options: &SearchOptions) -> io::Result<()> {
+    if options.show_header {
+        let separator = if options.null { Cow::Borrowed("\0") } else { paint_part(options, |colors| &colors.separator, ":") };
+        let file_name = paint_part(options, |colors| &colors.file_name, file_name);
+        write_line(&mut out, options.output_eol, format_args!("{}{}{}", file_name, separator, count))?;
+    }
+    else {
+        write_line(&mut out, options.output_eol, format_args!("{}", count))?;
+    }
+
+    end_record(&mut out, options)
+}
+
+/// Writes a bare file name for `-l`/`-L`, ended by a NUL instead of a line
+/// terminator under `--null` so the list can be fed to `xargs -0`.
+fn write_file_name<W: Write>(out: &mut W, file_name: &str, options: &SearchOptions) -> io::Result<()> {
+    let file_name = paint_part(options, |colors| &colors.file_name, file_name);
+
+    if options.null {
+        write!(out, "{}\0", file_name)?;
+    }
+    else {
+        write_line(out, options.output_eol, format_args!("{}", file_name))?;
+    }
+
+    end_record(out, options)
+}
+
+/// With `line_buffered`, pushes each finished record out right away so a
+/// pipeline downstream sees it without waiting for the buffer to fill.
+fn end_record<W: Write>(out: &mut W, options: &SearchOptions) -> io::Result<()> {
+    if options.line_buffered {
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Colors `text` as the part of the output `part` picks, when color is on.
+fn paint_part<'a>(options: &SearchOptions, part: fn(&Colors) -> &String, text: &'a str) -> Cow<'a, str> {
+    match &options.colors {
+        Some(colors) => color::paint(part(colors), text),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Colors each match in a selected `line`. Lines selected by `-v` have no
+/// matches to show, and with `--columns` only the searched slice is looked at.
+fn highlight_matches<'a>(matcher: &dyn Matcher, line: &'a str, options: &SearchOptions) -> Cow<'a, str> {
+    let Some(colors) = &options.colors else {
+        return Cow::Borrowed(line);
+    };
+
+    if options.invert_match || colors.matched.is_empty() {
+        return Cow::Borrowed(line);
+    }
+
+    let searched = options.columns.map_or(0..line.len(), |columns| columns.range(line));
+    let mut highlighted = String::with_capacity(line.len());
+    let mut written = 0;
+
+    for span in matcher.find_iter(&line[searched.clone()]).filter(|m| !m.is_empty()) {
+        let (start, end) = (searched.start + span.start, searched.start + span.end);
+        highlighted.push_str(&line[written..start]);
+        highlighted.push_str(&color::paint(&colors.matched, &line[start..end]));
+        written = end;
+    }
+    highlighted.push_str(&line[written..]);
+
+    Cow::Owned(highlighted)
+}
+
+/// Rewrites each match in a selected `line` with `template`. With color this
+/// is a preview: the old text stays in, struck out, ahead of its replacement.
+/// As with highlighting, `-v` lines are left alone and only the `--columns`
+/// slice is looked at.
+fn replace_matches<'a>(matcher: &dyn Matcher, line: &'a str, template: &str, options: &SearchOptions) -> Cow<'a, str> {
+    if options.invert_match {
+        return Cow::Borrowed(line);
+    }
+
+    let searched = options.columns.map_or(0..line.len(), |columns| columns.range(line));
+    let mut replaced = String::with_capacity(line.len());
+    let mut written = 0;
+
+    for (span, replacement) in matcher.replacements(&line[searched.clone()], template).filter(|(span, _)| !span.is_empty()) {
+        let start = searched.start + span.start;
+        replaced.push_str(&line[written..start]);
+        if let Some(colors) = &options.colors {
+            replaced.push_str(&color::paint(&colors.removed, &line[start..searched.start + span.end]));
+        }
+        replaced.push_str(&paint_part(options, |colors| &colors.inserted, &replacement));
+        written = searched.start + span.end;
+    }
+    replaced.push_str(&line[written..]);
+
+    Cow::Owned(replaced)
+}
+
+/// Every line of output goes through here so the terminator is applied consistently.
+fn write_line<W: Write>(out: &mut W, eol: OutputEol, line: fmt::Arguments) -> io::Result<()> {
+    out.write_fmt(line)?;
+    out.write_all(eol.as_bytes())
+}
+
+fn is_line_match(matcher: &dyn Matcher, line: &str, options: &SearchOptions) -> bool {
+    #[cfg(feature = "unicode-words")]
+    if options.match_whole_words {
+        return unicode_words::is_word_match(matcher, line);
+    }
+    #[cfg(not(feature = "unicode-words"))]
+    let _ = options;
+
+    matcher.is_match(line)
+}
+
+fn should_write_line(is_match: bool, invert_match: bool, count_matching_lines: bool) -> bool {
+    is_match != invert_match && !count_matching_lines
+}
+
+/// Modes that only report totals never write the lines themselves.
+fn totals_only(options: &SearchOptions) -> bool {
+    options.count_matching_lines || options.sum_matches || options.count_bytes || options.count_matches || options.files_with_matches || options.files_without_match || options.quiet
+}
+
+/// Whether lines are grouped under a file name heading rather than prefixed
+/// with it.
+fn uses_heading(options: &SearchOptions) -> bool {
+    options.heading && options.show_header && !options.no_header && !options.json && !totals_only(options)
+}
+
+fn build_prefix(file_name: &str, options: &SearchOptions, position: Position, separator: char) -> String {
+    let mut prefix = String::new();
+    let separator = separator.to_string();
+    let separator = paint_part(options, |colors| &colors.separator, &separator);
+
+    if options.show_header && !options.no_header && !uses_heading(options) {
+        prefix.push_str(&paint_part(options, |colors| &colors.file_name, file_name));
+        prefix.push_str(if options.null { "\0" } else { &separator });
+    }
+
+    if options.show_line_numbers {
+        prefix.push_str(&paint_part(options, |colors| &colors.line_number, &position.line_number.to_string()));
+        prefix.push_str(&separator);
+    }
+
+    if let Some(column) = position.column {
+        prefix.push_str(&paint_part(options, |colors| &colors.line_number, &column.to_string()));
+        prefix.push_str(&separator);
+    }
+
+    if options.byte_offset {
+        prefix.push_str(&paint_part(options, |colors| &colors.byte_offset, &position.byte_offset.to_string()));
+        prefix.push_str(&separator);
+    }
+
+    prefix
+}
+
+/// Records to match, each with the number of bytes it took up in the input,
+/// terminator included.
+type Records<'a> = Box<dyn Iterator<Item = io::Result<(Cow<'a, str>, u64)>> + 'a>;
+
+/// How much of a file is looked at for a NUL byte: what a `BufReader` holds
+/// by default, so mapped and buffered files are judged alike.
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// Splits `reader` into the records that get matched: lines, or NUL-ended
+/// records with `null_data`, followed past EOF with `tail`.
+fn read_records<'a, R: BufRead + 'a>(mut reader: R, options: &SearchOptions) -> Records<'a> {
+    let terminator = if options.null_data { b'\0' } else { b'\n' };
+    let raw: Box<dyn Iterator<Item = io::Result<Vec<u8>>> + 'a> = if options.tail {
+        Box::new(TailLines::new(reader, tail::POLL_INTERVAL, terminator))
+    }
+    else {
+        Box::new(std::iter::from_fn(move || {
+            let mut record = Vec::new();
+            match reader.read_until(terminator, &mut record) {
+                Ok(0) => None,
+                Ok(_) => Some(Ok(record)),
+                Err(err) => Some(Err(err)),
+            }
+        }))
+    };
+
+    Box::new(raw.map(move |record| {
+        record.map(|mut bytes| {
+            let len = bytes.len() as u64;
+            bytes.truncate(trim_terminator(&bytes, terminator).len());
+            (Cow::Owned(decode_record(bytes)), len)
+        })
+    }))
+}
+
+/// `read_records` for text already in memory. Records borrow from `bytes`
+/// unless they have invalid UTF-8 to replace.
+fn slice_records<'a>(mut bytes: &'a [u8], options: &SearchOptions) -> Records<'a> {
+    let terminator = if options.null_data { b'\0' } else { b'\n' };
+
+    Box::new(std::iter::from_fn(move || {
+        if bytes.is_empty() {
+            return None;
+        }
+
+        let len = memchr::memchr(terminator, bytes).map_or(bytes.len(), |end| end + 1);
+        let (record, rest) = bytes.split_at(len);
+        bytes = rest;
+        Some(Ok((String::from_utf8_lossy(trim_terminator(record, terminator)), len as u64)))
+    }))
+}
+
+/// `record` without its terminator, and without the `\r` before it when a
+/// Windows line ends in `\r\n`.
+fn trim_terminator(record: &[u8], terminator: u8) -> &[u8] {
+    match record.strip_suffix(&[terminator]) {
+        Some(line) if terminator == b'\n' => line.strip_suffix(b"\r").unwrap_or(line),
+        Some(line) => line,
+        None => record,
+    }
+}
+
+/// Turns a raw record into text, replacing invalid UTF-8 rather than failing
+/// so that Latin-1 or binary content can still be searched.
+fn decode_record(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+}
+
+/// A file opened for searching.
+enum Input {
+    /// A regular file mapped into memory, for `mmap`.
+    Mapped(Mmap),
+    Stream(Box<dyn BufRead>),
+}
+
+/// A file's contents, ready to be matched as UTF-8.
+enum Text<'a> {
+    /// Text already in memory, searched where it is.
+    Bytes(&'a [u8]),
+    Reader(Box<dyn BufRead + 'a>),
+}
+
+impl<'a> Text<'a> {
+    /// Where the first NUL byte near the start is, for the heuristic grep
+    /// uses: text files essentially never contain one.
+    fn find_nul(&mut self) -> io::Result<Option<usize>> {
+        let start = match self {
+            Text::Bytes(bytes) => &bytes[..bytes.len().min(BINARY_SNIFF_LEN)],
+            Text::Reader(reader) => reader.fill_buf()?,
+        };
+        Ok(memchr::memchr(0, start))
+    }
+
+    fn records(self, options: &SearchOptions) -> Records<'a> {
+        match self {
+            Text::Bytes(bytes) => slice_records(bytes, options),
+            Text::Reader(reader) => read_records(reader, options),
+        }
+    }
+
+    /// All of the text at once, for matching across lines.
+    fn read_all(self) -> io::Result<Cow<'a, str>> {
+        match self {
+            Text::Bytes(bytes) => Ok(String::from_utf8_lossy(bytes)),
+            Text::Reader(mut reader) => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                Ok(Cow::Owned(decode_record(bytes)))
+            }
+        }
+    }
+}
+
+/// Opens `path` for buffered streaming, or standard input for `-`. This is
+/// also the path special files (FIFOs, `/dev/stdin`) take, so it must not
+/// depend on the file's size.
+fn open_reader<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead>> {
+    if is_stdin(path.as_ref()) {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+
+    let file = File::open(path)?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+/// Opens a file to be searched: through the `pre` command, through its
+/// decompressor with `search_zip`, or mapped with `mmap` or when it's as big
+/// as `mmap_threshold`, otherwise as `open_reader` does.
+fn open_input(path: &Path, options: &SearchOptions) -> io::Result<Input> {
+    if is_stdin(path) {
+        debug!("reading standard input");
+        return open_reader(path).map(Input::Stream);
+    }
+
+    if let Some(pre) = &options.pre {
+        debug!("{}: reading the output of {}", path.display(), pre);
+        let file = File::open(path)?;
+        return Ok(Input::Stream(Box::new(BufReader::new(CommandReader::spawn(&[pre], path, file.into())?))));
+    }
+
+    if options.search_zip
+        && let Some(decompressor) = command::decompressor(path)
+    {
+        debug!("{}: decompressing with {}", path.display(), decompressor.join(" "));
+        return Ok(Input::Stream(Box::new(BufReader::new(CommandReader::spawn(decompressor, path, Stdio::null())?))));
+    }
+
+    // A followed file keeps growing past whatever was mapped.
+    if (options.mmap || options.mmap_threshold.is_some()) && !options.tail {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if metadata.is_file() && (options.mmap || options.mmap_threshold.is_some_and(|threshold| metadata.len() >= threshold)) {
+            // SAFETY: the mapping is only ever read. If another process
+            // truncates the file while it's searched, reading can still
+            // fault; that's the trade `mmap` asks for, as in other greps.
+            match unsafe { Mmap::map(&file) } {
+                Ok(map) => {
+                    debug!("{}: memory-mapped", path.display());
+                    return Ok(Input::Mapped(map));
+                }
+                Err(err) => debug!("{}: can't be memory-mapped: {}", path.display(), err),
+            }
+        }
+    }
+
+    debug!("{}: buffered reads", path.display());
+    open_reader(path).map(Input::Stream)
+}
+
+/// The encoding to read text starting with `start` as, and how long a byte
+/// order mark there is to drop. A mark picks the encoding unless one was
+/// given; a mark that disagrees with the given encoding is kept as text.
+fn input_encoding(start: &[u8], options: &SearchOptions) -> (Encoding, usize) {
+    match (options.encoding, encoding::sniff_bom(start)) {
+        (None, Some(detected)) => detected,
+        (Some(chosen), Some((detected, len))) if chosen == detected => (chosen, len),
+        (chosen, _) => (chosen.unwrap_or(Encoding::UTF_8), 0),
+    }
+}
+
+/// Gets `reader` ready for matching as UTF-8: drops a byte order mark, and
+/// transcodes from the encoding the options or the mark call for.
+fn decode_input<'a>(mut reader: Box<dyn BufRead + 'a>, options: &SearchOptions) -> io::Result<Box<dyn BufRead + 'a>> {
+    let (encoding, bom_len) = input_encoding(reader.fill_buf()?, options);
+    reader.consume(bom_len);
+
+    if encoding == Encoding::UTF_8 {
+        return Ok(reader);
+    }
+
+    debug!("decoding from {}", encoding);
+    Ok(Box::new(BufReader::new(encoding::decode_reader(reader, encoding))))
+}
+
+/// `decode_input` for a mapped file: UTF-8 is searched in place, and only
+/// other encodings go through a decoder.
+fn decode_bytes<'a>(bytes: &'a [u8], options: &SearchOptions) -> Text<'a> {
+    let (encoding, bom_len) = input_encoding(bytes, options);
+    let bytes = &bytes[bom_len..];
+
+    if encoding == Encoding::UTF_8 {
+        return Text::Bytes(bytes);
+    }
+
+    debug!("decoding from {}", encoding);
+    Text::Reader(Box::new(BufReader::new(encoding::decode_reader(bytes, encoding))))
+}
+
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN_FILE_NAME)
+}
+
+/// The name shown for `path` in headers and messages. Standard input goes by
+/// `label` when one is given.
+fn display_name<'a>(path: &'a Path, label: Option<&'a str>) -> Cow<'a, str> {
+    if is_stdin(path) {
+        Cow::Borrowed(label.unwrap_or(STDIN_LABEL))
+    }
+    else {
+        path.to_string_lossy()
+    }
+}
+
+/// `display_name`, with the path, found under `root`, rewritten as
+/// `path_format` asks. A path that can't be made absolute is shown as given.
+fn file_label<'a>(path: &'a Path, root: &Path, options: &'a SearchOptions) -> Cow<'a, str> {
+    let formatted = match options.path_format {
+        Some(format) if !is_stdin(path) => format_path(path, root, format).ok(),
+        _ => None,
+    };
+
+    match formatted {
+        Some(formatted) => Cow::Owned(formatted.to_string_lossy().into_owned()),
+        None => display_name(path, options.label.as_deref()),
+    }
+}
+
+/// `path` made absolute, or relative to the search root it was found under.
+/// A file named on the command line is its own root, so relative to it is
+/// just its name. `.` and `..` are resolved by name alone, without following
+/// symlinks.
+fn format_path(path: &Path, root: &Path, format: PathFormat) -> io::Result<PathBuf> {
+    let absolute = normalize_path(&std::path::absolute(path)?);
+
+    match format {
+        PathFormat::Absolute => Ok(absolute),
+        PathFormat::Relative => {
+            let root = normalize_path(&std::path::absolute(root)?);
+            let base = match root.parent() {
+                Some(parent) if root == absolute => parent.to_path_buf(),
+                _ => root,
+            };
+            Ok(relative_path(&absolute, &base))
+        }
+    }
+}
+
+/// `path` with its `.` components dropped and each `..` taking away the
+/// component before it.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// How to get to absolute `path` from absolute `base`. Paths with nothing in
+/// common, such as ones on different Windows drives, stay absolute.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let common = path.components().zip(base.components()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut relative: PathBuf = base.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(path.components().skip(common));
+    relative
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::io::{Write, BufRead};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_search_summary_to_json() {
+        let summary = SearchSummary {
+            files_searched: 3,
+            files_matched: 2,
+            total_matches: 5,
+            total_lines: 40,
+            total_selected_lines: 3,
+            total_bytes: 512,
+            had_errors: false,
+        };
+
+        assert_eq!(
+            summary.to_json(12),
+            r#"{"files_searched":3,"files_matched":2,"total_matches":5,"total_lines":40,"had_errors":false,"elapsed_ms":12}"#
+        );
+    }
+
+    #[test]
+    fn test_search_summary_to_stats() {
+        let summary = SearchSummary {
+            files_searched: 3,
+            files_matched: 2,
+            total_matches: 5,
+            total_selected_lines: 4,
+            total_bytes: 512,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            summary.to_stats(Duration::from_millis(1500), OutputEol::Lf),
+            "\n5 matches\n4 matched lines\n2 files contained matches\n3 files searched\n512 bytes searched\n1.500000 seconds\n"
+        );
+        assert!(summary.to_stats(Duration::from_millis(1500), OutputEol::Crlf).starts_with("\r\n5 matches\r\n4 matched lines\r\n"));
+    }
+
+    #[test]
+    fn test_list_files() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("sub"))?;
+        std::fs::write(dir.path().join("a.rs"), "fn main() {}\n")?;
+        std::fs::write(dir.path().join("b.txt"), "")?;
+        std::fs::write(dir.path().join("sub").join("c.rs"), "")?;
+
+        let options = SearchOptions {
+            recursive: true,
+            walk: WalkOptions { include: Some(build_glob_set(&["*.rs"]).unwrap()), ..Default::default() },
+            ..Default::default()
+        };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = list_files(&[dir.path()], &options, &mut buf)?;
+
+        let root = dir.path().display();
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{root}/a.rs\n{root}/sub/c.rs\n"));
+        assert_eq!(summary.files_matched, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_skips_directory() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello")?;
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = search(&[dir.path(), tmp.path()], &regex, &SearchOptions::default(), &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "hello\n");
+        assert_eq!(summary.files_searched, 1);
+        assert!(summary.had_errors);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_regex_without_insensitive() -> Result<()> {
+        let regex = build_regex("hello", &RegexOptions::default())?;
+
+        assert_eq!(regex.is_match("some text HELLO more text"), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_regex_with_insensitive() -> Result<()> {
+        let regex = build_regex("hello", &RegexOptions { insensitive: true, ..Default::default() })?;
+
+        assert_eq!(regex.is_match("some text HELLO more text"), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_regex_basic_pattern_type() -> Result<()> {
+        let regex = build_regex(r"\(foo\)\{2\}(", &RegexOptions { pattern_type: PatternType::Basic, ..Default::default() })?;
+
+        assert!(regex.is_match("foofoo("));
+        assert!(!regex.is_match("foo("));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_regex_starts_with() -> Result<()> {
+        let regex = build_regex("foo|bar", &RegexOptions { starts_with: true, ..Default::default() })?;
+
+        assert!(regex.is_match("bar baz"));
+        assert!(!regex.is_match("baz bar"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_regex_ends_with_insensitive() -> Result<()> {
+        let regex = build_regex("foo|bar", &RegexOptions { ends_with: true, insensitive: true, ..Default::default() })?;
+
+        assert!(regex.is_match("baz BAR"));
+        assert!(!regex.is_match("bar baz"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_regex_starts_with_and_ends_with() -> Result<()> {
+        let regex = build_regex("foo|bar", &RegexOptions { starts_with: true, ends_with: true, ..Default::default() })?;
+
+        assert!(regex.is_match("bar"));
+        assert!(!regex.is_match("foo bar"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_range_from_str() {
+        assert_eq!("10:40".parse(), Ok(ColumnRange { start: 10, end: Some(40) }));
+        assert_eq!(":40".parse(), Ok(ColumnRange { start: 1, end: Some(40) }));
+        assert_eq!("10:".parse(), Ok(ColumnRange { start: 10, end: None }));
+        assert!("0:4".parse::<ColumnRange>().is_err());
+        assert!("5:4".parse::<ColumnRange>().is_err());
+        assert!("5".parse::<ColumnRange>().is_err());
+    }
+
+    #[test]
+    fn test_column_range_slice() {
+        let columns = ColumnRange { start: 3, end: Some(5) };
+
+        assert_eq!(columns.slice("abcdefg"), "cde");
+        assert_eq!(columns.slice("abcd"), "cd");
+        assert_eq!(columns.slice("a"), "");
+    }
+
+    #[test]
+    fn test_column_range_slice_widens_to_char_boundaries() {
+        // "é" occupies columns 2-3, so a window of column 3 alone cuts it in half.
+        let columns = ColumnRange { start: 3, end: Some(3) };
+        assert_eq!(columns.slice("aéb"), "é");
+
+        let columns = ColumnRange { start: 2, end: Some(2) };
+        assert_eq!(columns.slice("aéb"), "é");
+    }
+
+    #[test]
+    fn test_build_prefix_with_header_without_line_numbers() -> Result<()> {
+        let prefix_with_header = build_prefix("some_file", &SearchOptions { show_header: true, ..Default::default() }, Position { line_number: 22, byte_offset: 0, column: None }, ':');
+
+        assert_eq!(prefix_with_header, "some_file:");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_prefix_without_header_without_line_numbers() -> Result<()> {
+        let prefix_with_header = build_prefix("some_file", &SearchOptions::default(), Position { line_number: 22, byte_offset: 0, column: None }, ':');
+
+        assert_eq!(prefix_with_header, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_prefix_with_header_with_line_numbers() -> Result<()> {
+        let prefix_with_header = build_prefix("some_file", &SearchOptions { show_header: true, show_line_numbers: true, ..Default::default() }, Position { line_number: 22, byte_offset: 0, column: None }, ':');
+
+        assert_eq!(prefix_with_header, "some_file:22:");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_prefix_without_header_with_line_numbers() -> Result<()> {
+        let prefix_with_header = build_prefix("some_file", &SearchOptions { show_line_numbers: true, ..Default::default() }, Position { line_number: 22, byte_offset: 0, column: None }, ':');
+
+        assert_eq!(prefix_with_header, "22:");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_prefix_with_header_with_line_numbers_context_separator() -> Result<()> {
+        let prefix_with_header = build_prefix("some_file", &SearchOptions { show_header: true, show_line_numbers: true, ..Default::default() }, Position { line_number: 22, byte_offset: 0, column: None }, '=');
+
+        assert_eq!(prefix_with_header, "some_file=22=");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_count_with_header() -> io::Result<()> {
+        let mut buf: Vec<u8> = Vec::new();
+        write_count(&mut buf, "some_file", 3, &SearchOptions { show_header: true, ..Default::default() })?;
+
+        assert_eq!(buf, b"some_file:3\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_count_without_header() -> io::Result<()> {
+        let mut buf: Vec<u8> = Vec::new();
+        write_count(&mut buf, "some_file", 3, &SearchOptions::default())?;
+
+        assert_eq!(buf, b"3\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_count_crlf() -> io::Result<()> {
+        let mut buf: Vec<u8> = Vec::new();
+        write_count(&mut buf, "some_file", 3, &SearchOptions { show_header: true, output_eol: OutputEol::Crlf, ..Default::default() })?;
+
+        assert_eq!(buf, b"some_file:3\r\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_count_null() -> io::Result<()> {
+        let mut buf: Vec<u8> = Vec::new();
+        write_count(&mut buf, "odd:name\n", 3, &SearchOptions { show_header: true, null: true, ..Default::default() })?;
+
+        assert_eq!(buf, b"odd:name\n\x003\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_reader_reads_file() -> io::Result<()> {
+        // 1. Create a temporary file
+        let mut tmpfile = NamedTempFile::new()?;
+
+        // 2. Write some content to it
+        writeln!(tmpfile, "hello world")?;
+        writeln!(tmpfile, "goodbye world")?;
+
+        // 3. Re-open the file through your function
+        let reader = open_reader(tmpfile.path())?;
+
+        // 4. Collect the lines and verify the content
+        let lines: Vec<_> = reader.lines().collect::<Result<_, _>>()?;
+        assert_eq!(lines, vec!["hello world", "goodbye world"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_name_for_stdin() {
+        assert_eq!(display_name(Path::new("-"), None), "(standard input)");
+        assert_eq!(display_name(Path::new("some_file"), None), "some_file");
+    }
+
+    #[test]
+    fn test_display_name_label_only_names_stdin() {
+        assert_eq!(display_name(Path::new("-"), Some("upstream")), "upstream");
+        assert_eq!(display_name(Path::new("some_file"), Some("upstream")), "some_file");
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(normalize_path(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(relative_path(Path::new("/a/b/c.txt"), Path::new("/a")), Path::new("b/c.txt"));
+        assert_eq!(relative_path(Path::new("/a/b/c.txt"), Path::new("/a/d/e")), Path::new("../../b/c.txt"));
+    }
+
+    #[test]
+    fn test_open_reader_nonexistent_file() {
+        // 1. Pick a definitely-nonexistent file path
+        let bogus_path = "this_file_should_not_exist_12345.txt";
+
+        // 2. Call your function
+        let result = open_reader(bogus_path);
+
+        // 3. Verify it failed
+        assert!(result.is_err(), "Expected error for nonexistent file, got Ok");
+
+        // 4. Optionally: check the specific error kind
+        if let Err(err) = result {
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_file_name_reads_fifo() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fifo_path = dir.path().join("fifo");
+        let status = std::process::Command::new("mkfifo").arg(&fifo_path).status()?;
+        assert!(status.success(), "mkfifo failed");
+
+        let writer_path = fifo_path.clone();
+        let writer = std::thread::spawn(move || -> std::io::Result<()> {
+            let mut fifo = std::fs::OpenOptions::new().write(true).open(writer_path)?;
+            writeln!(fifo, "hello")?;
+            writeln!(fifo, "world")?;
+            Ok(())
+        });
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&fifo_path, &regex, &SearchOptions::default(), &mut buf)?;
+        writer.join().unwrap()?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "hello\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_matches_lines() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello")?;
+        writeln!(tmp, "world")?;
+        writeln!(tmp, "HELLO")?;
+        // Flush/close the file handle so reads see it
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap(); // case-sensitive
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &SearchOptions::default(), &mut buf)?;
+
+        let out = String::from_utf8(buf).expect("output was not valid UTF-8");
+        assert!(out.contains("hello"));
+        assert!(!out.contains("world"));
+        // "HELLO" only matches if case-insensitive; here it should not.
+        assert!(!out.contains("HELLO"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_returns_file_summary() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello hello")?;
+        writeln!(tmp, "world")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = process_file_name(&path, &regex, &SearchOptions::default(), &mut buf)?;
+
+        assert_eq!(summary, FileSummary { lines: 2, matching_lines: 1, selected_lines: 1, matches: 2, bytes: 18 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_matches_lines_count_matching() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello")?;
+        writeln!(tmp, "world")?;
+        writeln!(tmp, "HELLO")?;
+        // Flush/close the file handle so reads see it
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap(); // case-sensitive
+
+        let mut buf: Vec<u8> = Vec::new();
+        let options = SearchOptions { count_matching_lines: true, ..Default::default() };
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).expect("output was not valid UTF-8");
+        println!("out = {}", out);
+        assert!(!out.contains("hello"));
+        assert!(!out.contains("world"));
+        // "HELLO" only matches if case-insensitive; here it should not.
+        assert!(!out.contains("HELLO"));
+        assert!(out.contains("1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_matches_lines_count_matching_with_header() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello")?;
+        writeln!(tmp, "world")?;
+        writeln!(tmp, "HELLO")?;
+        // Flush/close the file handle so reads see it
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap(); // case-sensitive
+
+        let mut buf: Vec<u8> = Vec::new();
+        let options = SearchOptions { show_header: true, count_matching_lines: true, ..Default::default() };
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).expect("output was not valid UTF-8");
+
+        let filename = path.to_str().unwrap();
+
+        println!("out = {}", out);
+        assert!(!out.contains("hello"));
+        assert!(!out.contains("world"));
+        // "HELLO" only matches if case-insensitive; here it should not.
+        assert!(!out.contains("HELLO"));
+        assert!(out.contains("1"));
+        assert!(out.contains(&format!("{}:1", filename)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_count_nonzero_skips_files_without_matches() -> std::io::Result<()> {
+        let mut first = NamedTempFile::new()?;
+        writeln!(first, "hello")?;
+        writeln!(first, "hello again")?;
+        let mut second = NamedTempFile::new()?;
+        writeln!(second, "world")?;
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { show_header: true, count_matching_lines: true, count_nonzero: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        search(&[first.path(), second.path()], &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).unwrap();
+        assert_eq!(out, format!("{}:2\n", first.path().to_str().unwrap()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_matched_bytes() -> Result<()> {
+        let regex = build_regex("[A-Za-z0-9+/]+=*", &RegexOptions::default())?;
+
+        assert_eq!(count_matched_bytes(&regex, "aGVsbG8= and d29ybGQ="), 8 + 3 + 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_count_bytes_with_header() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "id=abc id=de")?;
+        writeln!(tmp, "nothing here")?;
+        writeln!(tmp, "id=f")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("id=[a-z]+", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { show_header: true, count_bytes: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).unwrap();
+        assert_eq!(out, format!("{}:{}\n", path.to_str().unwrap(), 6 + 5 + 4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_count_matches() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "id=abc id=de")?;
+        writeln!(tmp, "nothing here")?;
+        writeln!(tmp, "id=f")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("id=[a-z]+", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { show_header: true, count_matches: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{}:3\n", path.to_str().unwrap()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_with_callback_collects_matches() -> Result<()> {
+        let input = "foo bar foo\nbaz\nfood\n";
+        let regex = build_regex("foo", &RegexOptions::default())?;
+
+        let mut collected: Vec<(u64, String, Vec<Range<usize>>)> = Vec::new();
+        let summary = search_with_callback(input.as_bytes(), &regex, &SearchOptions::default(), |result| {
+            collected.push((result.line_number, result.line.to_string(), result.matches.clone()));
+        }, |_| {})?;
+
+        assert_eq!(collected, vec![
+            (1, "foo bar foo".to_string(), vec![0..3, 8..11]),
+            (3, "food".to_string(), vec![Range { start: 0, end: 3 }]),
+        ]);
+        assert_eq!(summary, FileSummary { lines: 3, matching_lines: 2, selected_lines: 2, matches: 3, bytes: 21 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_with_callback_columns_offsets_and_invert() -> Result<()> {
+        let input = "xxfoo foo\nbar\n";
+        let regex = build_regex("foo", &RegexOptions::default())?;
+
+        let mut matches = Vec::new();
+        let options = SearchOptions { columns: Some(ColumnRange { start: 3, end: Some(5) }), ..Default::default() };
+        search_with_callback(input.as_bytes(), &regex, &options, |result| matches.push(result.matches.clone()), |_| {})?;
+        assert_eq!(matches, vec![vec![Range { start: 2, end: 5 }]]);
+
+        let mut inverted = Vec::new();
+        let options = SearchOptions { invert_match: true, ..Default::default() };
+        search_with_callback(input.as_bytes(), &regex, &options, |result| inverted.push((result.line_number, result.matches.is_empty())), |_| {})?;
+        assert_eq!(inverted, vec![(2, true)]);
+
+        Ok(())
+    }
+
+    /// What `search_with_callback` hands over for `input`, written the way
+    /// `process_file_name` writes lines with `-n`, next to what that writes.
+    fn callback_and_cli_output(input: &[u8], pattern: &str, options: &SearchOptions) -> Result<(String, Vec<Diagnostic>, String)> {
+        let mut tmp = NamedTempFile::new()?;
+        tmp.write_all(input)?;
+        let regex_options = RegexOptions { multiline: options.multiline, ..Default::default() };
+        let matcher = build_matcher(&[pattern], &regex_options)?;
+
+        let mut lines = String::new();
+        let mut diagnostics = Vec::new();
+        let from_callback = search_with_callback(
+            input,
+            matcher.as_ref(),
+            options,
+            |result| lines.push_str(&format!("{}:{}\n", result.line_number, result.line)),
+            |diagnostic| diagnostics.push(diagnostic.clone()),
+        )?;
+
+        let mut buf: Vec<u8> = Vec::new();
+        let from_cli = process_file_name(tmp.path(), matcher.as_ref(), options, &mut buf)?;
+        assert_eq!(from_callback, from_cli);
+
+        Ok((lines, diagnostics, String::from_utf8(buf)?))
+    }
+
+    #[test]
+    fn test_search_with_callback_matches_cli_output() -> Result<()> {
+        let max_count = SearchOptions { show_line_numbers: true, max_count: Some(2), ..Default::default() };
+        let (lines, diagnostics, out) = callback_and_cli_output(b"foo 1\nbar\nfoo 2\nfoo 3\n", "foo", &max_count)?;
+        assert_eq!(lines, out);
+        assert_eq!(out, "1:foo 1\n3:foo 2\n");
+        assert_eq!(diagnostics, [Diagnostic::MaxCountReached]);
+
+        let multiline = SearchOptions { show_line_numbers: true, multiline: true, ..Default::default() };
+        let (lines, diagnostics, out) = callback_and_cli_output(b"a\nstart\nend\nb\n", "start\nend", &multiline)?;
+        assert_eq!(lines, out);
+        assert_eq!(out, "2:start\n3:end\n");
+        assert!(diagnostics.is_empty());
+
+        let marked = SearchOptions { show_line_numbers: true, ..Default::default() };
+        let (lines, _, out) = callback_and_cli_output(b"\xEF\xBB\xBFfoo\n", "^foo$", &marked)?;
+        assert_eq!(lines, out);
+        assert_eq!(out, "1:foo\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_with_callback_binary_input() -> Result<()> {
+        let input = b"\x7fELF\0\nsymbol foo\nfoo again\n";
+
+        let (lines, diagnostics, out) = callback_and_cli_output(input, "foo", &SearchOptions::default())?;
+        assert_eq!(lines, "");
+        assert_eq!(diagnostics, [Diagnostic::BinaryMatches { offset: 13, nul_offset: 4 }]);
+        assert!(out.starts_with("Binary file ") && out.ends_with(" matches\n"));
+
+        let skip = SearchOptions { binary_files: BinaryFiles::WithoutMatch, ..Default::default() };
+        let (lines, diagnostics, out) = callback_and_cli_output(input, "foo", &skip)?;
+        assert_eq!((lines.as_str(), out.as_str()), ("", ""));
+        assert_eq!(diagnostics, [Diagnostic::BinarySkipped]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_with_header() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "foo")?;
+        writeln!(tmp, "bar")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("foo", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        let options = SearchOptions { show_header: true, ..Default::default() };
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).unwrap();
+        // Expect the prefix (filename:) and the matched line
+        let filename = path.to_str().unwrap();
+        assert!(out.contains(&format!("{}:foo", filename)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_match_filenames_name_only() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("needle.txt");
+        std::fs::write(&path, "hay\n")?;
+
+        let regex = build_regex("needle", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { match_filenames: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).unwrap();
+        assert_eq!(out, format!("File name {} matches\n", path.to_str().unwrap()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_match_filenames_content_only() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("hay.txt");
+        std::fs::write(&path, "needle\n")?;
+
+        let regex = build_regex("needle", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { match_filenames: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).unwrap();
+        assert_eq!(out, "needle\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_output_eol_crlf() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "foo")?;
+        writeln!(tmp, "bar")?;
+        writeln!(tmp, "food")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("foo", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { output_eol: OutputEol::Crlf, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        assert_eq!(buf, b"foo\r\nfood\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_columns() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "INFO  ERROR in payload")?;
+        writeln!(tmp, "ERROR something broke")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("ERROR", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { columns: Some(ColumnRange { start: 1, end: Some(5) }), ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        // The full line is printed even though only columns 1-5 were searched.
+        assert_eq!(String::from_utf8(buf).unwrap(), "ERROR something broke\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_no_matches_outputs_nothing() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "alpha")?;
+        writeln!(tmp, "beta")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("zzz", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &SearchOptions::default(), &mut buf)?;
+
+        assert!(buf.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_show_context_line() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "fn first() {{")?;
+        writeln!(tmp, "    let a = 1;")?;
+        writeln!(tmp, "}}")?;
+        writeln!(tmp, "pub fn second() {{")?;
+        writeln!(tmp, "    let target = 2;")?;
+        writeln!(tmp, "    let target_again = 3;")?;
+        writeln!(tmp, "}}")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("target", &RegexOptions::default()).unwrap();
+        let context_line_regex = Regex::new(DEFAULT_CONTEXT_LINE_REGEX).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        let options = SearchOptions { show_line_numbers: true, context_line_regex: Some(context_line_regex), ..Default::default() };
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).unwrap();
+        assert_eq!(out, "4=pub fn second() {\n5:    let target = 2;\n6:    let target_again = 3;\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_show_context_line_matching_itself() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "fn target() {{")?;
+        writeln!(tmp, "}}")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("target", &RegexOptions::default()).unwrap();
+        let context_line_regex = Regex::new(DEFAULT_CONTEXT_LINE_REGEX).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        let options = SearchOptions { context_line_regex: Some(context_line_regex), ..Default::default() };
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let out = String::from_utf8(buf).unwrap();
+        assert_eq!(out, "fn target() {\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_matches_multiple_per_line() -> Result<()> {
+        let regex = build_regex("ab", &RegexOptions::default())?;
+
+        assert_eq!(count_matches(&regex, "ab cab abab"), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_sum_matches_across_files() -> std::io::Result<()> {
+        let mut first = NamedTempFile::new()?;
+        writeln!(first, "old_api(); old_api();")?;
+        writeln!(first, "new_api();")?;
+        let mut second = NamedTempFile::new()?;
+        writeln!(second, "old_api();")?;
+
+        let regex = build_regex("old_api", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { sum_matches: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        search(&[first.path(), second.path()], &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "3\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_summary_across_files() -> std::io::Result<()> {
+        let mut first = NamedTempFile::new()?;
+        writeln!(first, "foo foo")?;
+        writeln!(first, "bar")?;
+        writeln!(first, "foo")?;
+        let mut second = NamedTempFile::new()?;
+        writeln!(second, "bar")?;
+        let missing = first.path().with_extension("missing");
+
+        let regex = build_regex("foo", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = search(&[first.path(), second.path(), &missing], &regex, &SearchOptions::default(), &mut buf)?;
+
+        assert_eq!(summary, SearchSummary {
+            files_searched: 2,
+            files_matched: 1,
+            total_matches: 3,
+            total_lines: 4,
+            total_selected_lines: 2,
+            total_bytes: 20,
+            had_errors: true,
+        });
+        Ok(())
+    }
+
+    fn empty_pattern_output(options: &SearchOptions) -> std::io::Result<String> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "alpha")?;
+        writeln!(tmp)?;
+        writeln!(tmp, "beta")?;
+
+        let regex = build_regex("", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        search(&[tmp.path()], &regex, options, &mut buf)?;
+
+        Ok(String::from_utf8(buf).unwrap())
+    }
+
+    #[test]
+    fn test_empty_pattern_matches_every_line() -> std::io::Result<()> {
+        let out = empty_pattern_output(&SearchOptions::default())?;
+
+        assert_eq!(out, "alpha\n\nbeta\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_pattern_counts_every_line() -> std::io::Result<()> {
+        let out = empty_pattern_output(&SearchOptions { count_matching_lines: true, ..Default::default() })?;
+
+        assert_eq!(out, "3\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_pattern_inverted_selects_nothing() -> std::io::Result<()> {
+        let out = empty_pattern_output(&SearchOptions { invert_match: true, ..Default::default() })?;
+
+        assert_eq!(out, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_pattern_has_no_individual_matches() -> std::io::Result<()> {
+        // Zero-width matches are never counted as -o style matches, so this can't run away.
+        let out = empty_pattern_output(&SearchOptions { sum_matches: true, ..Default::default() })?;
+
+        assert_eq!(out, "0\n");
+        assert_eq!(count_matches(&FixedStringMatcher::new(""), "abc"), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_fixed_pattern_multibyte() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "é")?;
+        let regex_options = RegexOptions { pattern_type: PatternType::Fixed, ..Default::default() };
+        let matcher = build_matcher(&[""], &regex_options).unwrap();
+
+        let output = |options: SearchOptions| -> std::io::Result<String> {
+            let mut buf: Vec<u8> = Vec::new();
+            search(&[tmp.path()], matcher.as_ref(), &options, &mut buf)?;
+            Ok(String::from_utf8(buf).unwrap())
+        };
+
+        assert_eq!(output(SearchOptions::default())?, "é\n");
+        assert_eq!(output(SearchOptions { only_matching: true, ..Default::default() })?, "");
+        assert_eq!(output(SearchOptions { replace: Some(String::from("X")), ..Default::default() })?, "é\n");
+        assert_eq!(FixedStringMatcher::new("").find_iter("é").collect::<Vec<_>>(), vec![0..0, 2..2]);
+        Ok(())
+    }
+
+    fn context_output(lines: &[&str], pattern: &str, before_context: usize, after_context: usize) -> std::io::Result<String> {
+        let mut tmp = NamedTempFile::new()?;
+        for line in lines {
+            writeln!(tmp, "{}", line)?;
+        }
+
+        let regex = build_regex(pattern, &RegexOptions::default()).unwrap();
+        let options = SearchOptions { show_line_numbers: true, before_context, after_context, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        Ok(String::from_utf8(buf).unwrap())
+    }
+
+    #[test]
+    fn test_context_before_and_after() -> std::io::Result<()> {
+        let out = context_output(&["a", "b", "match", "c", "d", "e"], "match", 1, 1)?;
+
+        assert_eq!(out, "2-b\n3:match\n4-c\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_context_separates_non_adjacent_groups() -> std::io::Result<()> {
+        let out = context_output(&["match", "a", "b", "c", "match", "d"], "match", 0, 1)?;
+
+        assert_eq!(out, "1:match\n2-a\n--\n5:match\n6-d\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_context_merges_overlapping_groups() -> std::io::Result<()> {
+        let out = context_output(&["a", "match", "b", "match", "c"], "match", 1, 1)?;
+
+        assert_eq!(out, "1-a\n2:match\n3-b\n4:match\n5-c\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_context_before_clamps_at_start_of_file() -> std::io::Result<()> {
+        let out = context_output(&["a", "match", "b"], "match", 5, 0)?;
+
+        assert_eq!(out, "1-a\n2:match\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_context_before_on_first_line() -> std::io::Result<()> {
+        let out = context_output(&["match", "a"], "match", 3, 0)?;
+
+        assert_eq!(out, "1:match\n");
+        Ok(())
+    }
+
+    fn group_separator_output(group_separator: Option<&str>, no_group_separator: bool) -> std::io::Result<String> {
+        let mut tmp = NamedTempFile::new()?;
+        for line in ["match", "a", "b", "match"] {
+            writeln!(tmp, "{}", line)?;
+        }
+
+        let regex = build_regex("match", &RegexOptions::default()).unwrap();
+        let options = SearchOptions {
+            after_context: 1,
+            group_separator: group_separator.map(String::from),
+            no_group_separator,
+            ..Default::default()
+        };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        Ok(String::from_utf8(buf).unwrap())
+    }
+
+    #[test]
+    fn test_custom_group_separator() -> std::io::Result<()> {
+        assert_eq!(group_separator_output(None, false)?, "match\na\n--\nmatch\n");
+        assert_eq!(group_separator_output(Some("~~~"), false)?, "match\na\n~~~\nmatch\n");
+        assert_eq!(group_separator_output(Some(""), false)?, "match\na\n\nmatch\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_group_separator() -> std::io::Result<()> {
+        assert_eq!(group_separator_output(Some("~~~"), true)?, "match\na\nmatch\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_context_after_on_last_line() -> std::io::Result<()> {
+        let out = context_output(&["a", "match"], "match", 0, 5)?;
+
+        assert_eq!(out, "2:match\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_with_context() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        for line in ["a", "b", "match", "c", "match", "d", "e", "f", "match", "g"] {
+            writeln!(tmp, "{}", line)?;
+        }
+        let regex = build_regex("match", &RegexOptions::default()).unwrap();
+
+        // Lines 2-6 (line 4 is context to both of the first two matches) and 8-10.
+        let count = |before_context, after_context| -> std::io::Result<String> {
+            let options = SearchOptions { count_with_context: true, before_context, after_context, ..Default::default() };
+            let mut buf: Vec<u8> = Vec::new();
+            process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+            Ok(String::from_utf8(buf).unwrap())
+        };
+
+        assert_eq!(count(1, 1)?, "8\n");
+        assert_eq!(count(0, 0)?, "3\n");
+        assert_eq!(count(5, 0)?, "9\n");
+        assert_eq!(count(0, 9)?, "8\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_context_ignored_when_counting() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "a")?;
+        writeln!(tmp, "match")?;
+        writeln!(tmp, "b")?;
+
+        let regex = build_regex("match", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { count_matching_lines: true, before_context: 3, after_context: 3, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_only_matching() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "id=1 name=x id=22")?;
+        writeln!(tmp, "nothing")?;
+        writeln!(tmp, "id=333")?;
+        let path = tmp.path().to_path_buf();
+
+        let regex = build_regex("id=[0-9]+", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { show_header: true, show_line_numbers: true, only_matching: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(&path, &regex, &options, &mut buf)?;
+
+        let name = path.to_str().unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{name}:1:id=1\n{name}:1:id=22\n{name}:3:id=333\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_join_matches() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "x id=1 name=x id=22 id=4")?;
+        writeln!(tmp, "nothing")?;
+        writeln!(tmp, "id=333")?;
+
+        let regex = build_regex("id=[0-9]+", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { byte_offset: true, only_matching: true, join_matches: Some(", ".to_string()), ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "2:id=1, id=22, id=4\n33:id=333\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_replace() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "id=1 name=x id=22")?;
+        writeln!(tmp, "nothing")?;
+        let regex = build_regex("id=([0-9]+)", &RegexOptions::default()).unwrap();
+
+        let output = |options: SearchOptions| -> std::io::Result<String> {
+            let mut buf: Vec<u8> = Vec::new();
+            process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+            Ok(String::from_utf8(buf).unwrap())
+        };
+
+        let replace = Some(String::from("<$1>"));
+        assert_eq!(output(SearchOptions { replace: replace.clone(), ..Default::default() })?, "<1> name=x <22>\n");
+        assert_eq!(output(SearchOptions { replace: replace.clone(), only_matching: true, ..Default::default() })?, "<1>\n<22>\n");
+
+        let colors = Some(Colors::default());
+        assert_eq!(
+            output(SearchOptions { replace, colors, ..Default::default() })?,
+            "\x1b[09;31m\x1b[Kid=1\x1b[m\x1b[K\x1b[32m\x1b[K<1>\x1b[m\x1b[K name=x \x1b[09;31m\x1b[Kid=22\x1b[m\x1b[K\x1b[32m\x1b[K<22>\x1b[m\x1b[K\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_multiline() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        write!(tmp, "start\nfn foo(\n    a: u32,\n) {{}}\nfn foo(b: u32) {{}}\n")?;
+
+        let regex_options = RegexOptions { multiline: true, ..Default::default() };
+        let regex = build_regex(r"fn foo\(\n[^)]*\)", &regex_options).unwrap();
+        let options = SearchOptions { show_line_numbers: true, multiline: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "2:fn foo(\n3:    a: u32,\n4:) {}\n");
+        assert_eq!(summary.selected_lines, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_strips_byte_order_marks() -> std::io::Result<()> {
+        let regex = build_regex("^first$", &RegexOptions::default()).unwrap();
+        let utf16: Vec<u8> = "\u{FEFF}first\nsecond\n".encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+        for contents in [b"\xEF\xBB\xBFfirst\nsecond\n".to_vec(), utf16] {
+            let mut tmp = NamedTempFile::new()?;
+            tmp.write_all(&contents)?;
+
+            let mut buf: Vec<u8> = Vec::new();
+            process_file_name(tmp.path(), &regex, &SearchOptions::default(), &mut buf)?;
+            assert_eq!(String::from_utf8(buf).unwrap(), "first\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_mmap_matches_buffered() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        write!(tmp, "alpha\r\nbeta\ngamma beta")?;
+        let mut marked = NamedTempFile::new()?;
+        marked.write_all(b"\xEF\xBB\xBFbeta\ncaf\xe9 beta\n")?;
+        let mut utf16 = NamedTempFile::new()?;
+        utf16.write_all(&"\u{FEFF}alpha\nbeta\n".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<u8>>())?;
+        let empty = NamedTempFile::new()?;
+        let regex = build_regex("beta|^$", &RegexOptions::default()).unwrap();
+
+        for path in [tmp.path(), marked.path(), utf16.path(), empty.path()] {
+            let output = |mmap| -> std::io::Result<Vec<u8>> {
+                let mut buf: Vec<u8> = Vec::new();
+                process_file_name(path, &regex, &SearchOptions { mmap, show_line_numbers: true, byte_offset: true, ..Default::default() }, &mut buf)?;
+                Ok(buf)
+            };
+            assert_eq!(output(true)?, output(false)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_slice_records() {
+        let records: Vec<_> = slice_records(b"one\r\nt\xffo\nthree", &SearchOptions::default()).map(Result::unwrap).collect();
+
+        assert_eq!(records, [(Cow::from("one"), 5), (Cow::from("t\u{FFFD}o"), 4), (Cow::from("three"), 5)]);
+        assert!(matches!(records[0].0, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_process_file_name_vimgrep() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "no")?;
+        writeln!(tmp, "a foo and foo")?;
+        let regex = build_regex("foo", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { vimgrep: true, show_header: true, show_line_numbers: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        let name = tmp.path().display();
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{name}:2:3:a foo and foo\n{name}:2:11:a foo and foo\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_column() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "a foo and foo")?;
+        writeln!(tmp, "no")?;
+        let regex = build_regex("foo", &RegexOptions::default()).unwrap();
+
+        let output = |options: SearchOptions| -> std::io::Result<String> {
+            let mut buf: Vec<u8> = Vec::new();
+            process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+            Ok(String::from_utf8(buf).unwrap())
+        };
+
+        assert_eq!(output(SearchOptions { column: true, show_line_numbers: true, ..Default::default() })?, "1:3:a foo and foo\n");
+        assert_eq!(output(SearchOptions { column: true, only_matching: true, ..Default::default() })?, "3:foo\n11:foo\n");
+        assert_eq!(output(SearchOptions { column: true, invert_match: true, ..Default::default() })?, "1:no\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_passthru() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "start")?;
+        writeln!(tmp, "ERROR one")?;
+        writeln!(tmp, "end")?;
+        let regex = build_regex("ERROR", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { passthru: true, show_line_numbers: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "1-start\n2:ERROR one\n3-end\n");
+        assert_eq!(summary.selected_lines, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_only_matching_inverted_prints_nothing() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "foo")?;
+        writeln!(tmp, "bar")?;
+
+        let regex = build_regex("foo", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { invert_match: true, only_matching: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert!(buf.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_files_with_matches() -> std::io::Result<()> {
+        let mut first = NamedTempFile::new()?;
+        writeln!(first, "hello")?;
+        writeln!(first, "hello again")?;
+        let mut second = NamedTempFile::new()?;
+        writeln!(second, "world")?;
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { show_header: true, count_matching_lines: true, files_with_matches: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        search(&[first.path(), second.path()], &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{}\n", first.path().to_str().unwrap()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_name_files_with_matches_stops_at_first_match() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello")?;
+        writeln!(tmp, "world")?;
+        writeln!(tmp, "more")?;
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { files_with_matches: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert_eq!(summary.lines, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_files_without_match() -> std::io::Result<()> {
+        let mut first = NamedTempFile::new()?;
+        writeln!(first, "setting=on")?;
+        let mut second = NamedTempFile::new()?;
+        writeln!(second, "other=off")?;
+
+        let regex = build_regex("^setting=", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { show_header: true, files_without_match: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        search(&[first.path(), second.path()], &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{}\n", second.path().to_str().unwrap()));
+        Ok(())
+    }
+
+    fn max_count_output(options: &SearchOptions) -> std::io::Result<(String, FileSummary)> {
+        let mut tmp = NamedTempFile::new()?;
+        for line in ["a1", "b", "a2", "c", "a3", "d"] {
+            writeln!(tmp, "{}", line)?;
+        }
+
+        let regex = build_regex("a", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = process_file_name(tmp.path(), &regex, options, &mut buf)?;
+
+        Ok((String::from_utf8(buf).unwrap(), summary))
+    }
+
+    #[test]
+    fn test_max_count_stops_reading() -> std::io::Result<()> {
+        let (out, summary) = max_count_output(&SearchOptions { max_count: Some(2), ..Default::default() })?;
+
+        assert_eq!(out, "a1\na2\n");
+        assert_eq!(summary.lines, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_count_zero_reads_nothing() -> std::io::Result<()> {
+        let (out, summary) = max_count_output(&SearchOptions { max_count: Some(0), ..Default::default() })?;
+
+        assert_eq!(out, "");
+        assert_eq!(summary.lines, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_count_inverted_count() -> std::io::Result<()> {
+        let options = SearchOptions { max_count: Some(2), invert_match: true, count_matching_lines: true, ..Default::default() };
+        let (out, summary) = max_count_output(&options)?;
+
+        assert_eq!(out, "2\n");
+        assert_eq!(summary.selected_lines, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_inverted_counts_selected_lines() -> std::io::Result<()> {
+        let options = SearchOptions { invert_match: true, count_matching_lines: true, ..Default::default() };
+        let (out, _) = max_count_output(&options)?;
+
+        assert_eq!(out, "3\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_count_invert_count_matrix() -> std::io::Result<()> {
+        // Lines are a1 b a2 c a3 d: three match `a`, three don't.
+        let cases = [
+            (None, false, "a1\na2\na3\n", "3\n"),
+            (None, true, "b\nc\nd\n", "3\n"),
+            (Some(0), false, "", "0\n"),
+            (Some(0), true, "", "0\n"),
+            (Some(1), false, "a1\n", "1\n"),
+            (Some(1), true, "b\n", "1\n"),
+            (Some(2), false, "a1\na2\n", "2\n"),
+            (Some(2), true, "b\nc\n", "2\n"),
+            (Some(5), false, "a1\na2\na3\n", "3\n"),
+            (Some(5), true, "b\nc\nd\n", "3\n"),
+        ];
+
+        for (max_count, invert_match, lines, count) in cases {
+            let options = SearchOptions { max_count, invert_match, ..Default::default() };
+            assert_eq!(max_count_output(&options)?.0, lines, "-m {:?} -v {}", max_count, invert_match);
+
+            let options = SearchOptions { max_count, invert_match, count_matching_lines: true, ..Default::default() };
+            assert_eq!(max_count_output(&options)?.0, count, "-m {:?} -v {} -c", max_count, invert_match);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_count_keeps_trailing_context() -> std::io::Result<()> {
+        let options = SearchOptions { max_count: Some(1), after_context: 2, show_line_numbers: true, ..Default::default() };
+        let (out, _) = max_count_output(&options)?;
+
+        // The second match falls inside the trailing context, so it prints as context.
+        assert_eq!(out, "1:a1\n2-b\n3-a2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_regex_word_regexp() {
+        let options = RegexOptions { word_regexp: true, ..Default::default() };
+        let regex = build_regex("foo", &options).unwrap();
+
+        assert!(regex.is_match("a foo b"));
+        assert!(regex.is_match("foo"));
+        assert!(!regex.is_match("foobar"));
+        assert!(!regex.is_match("a_foo"));
+    }
+
+    #[test]
+    fn test_build_regex_word_regexp_non_word_edges() {
+        let options = RegexOptions { word_regexp: true, ..Default::default() };
+
+        let regex = build_regex("@foo", &options).unwrap();
+        assert!(regex.is_match("mail @foo now"));
+        assert!(!regex.is_match("mail x@foo now"));
+
+        let regex = build_regex(r"foo\(\)", &options).unwrap();
+        assert!(regex.is_match("call foo() here"));
+        assert!(!regex.is_match("call foo()bar"));
+    }
+
+    #[test]
+    fn test_build_regex_word_regexp_tries_later_matches() {
+        let options = RegexOptions { word_regexp: true, ..Default::default() };
+        let regex = build_regex("fo+", &options).unwrap();
+
+        assert_eq!(regex.find("foobar fooo").map(|m| m.range()), Some(7..11));
+    }
+
+    #[test]
+    fn test_build_regex_line_regexp() {
+        let options = RegexOptions { line_regexp: true, insensitive: true, ..Default::default() };
+        let regex = build_regex("foo|bar", &options).unwrap();
+
+        assert!(regex.is_match("FOO"));
+        assert!(regex.is_match("bar"));
+        assert!(!regex.is_match("foobar"));
+        assert!(!regex.is_match(" foo"));
+    }
+
+    #[test]
+    fn test_line_regexp_inverted_count() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "foo")?;
+        writeln!(tmp, "foo bar")?;
+        writeln!(tmp, "bar")?;
+
+        let regex = build_regex("foo", &RegexOptions { line_regexp: true, ..Default::default() }).unwrap();
+        let options = SearchOptions { invert_match: true, count_matching_lines: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_uppercase_literal() {
+        assert!(has_uppercase_literal("Foo", PatternType::Extended));
+        assert!(has_uppercase_literal("[A-Z]x", PatternType::Extended));
+        assert!(!has_uppercase_literal("foo", PatternType::Extended));
+        assert!(!has_uppercase_literal(r"\S+\W\B\pL\p{Lu}\x4F\u{1F600}", PatternType::Extended));
+        assert!(!has_uppercase_literal("[[:upper:]]+", PatternType::Extended));
+        assert!(has_uppercase_literal(r"\SX", PatternType::Basic));
+        assert!(has_uppercase_literal(r"\S", PatternType::Fixed));
+    }
+
+    #[test]
+    fn test_describe_prefilter() {
+        let options = RegexOptions::default();
+
+        assert_eq!(describe_prefilter("foo|bar", &options), r#""foo", "bar""#);
+        assert_eq!(describe_prefilter(r"\w+foo", &options), "none");
+        assert!(describe_prefilter("(", &options).starts_with("none ("));
+    }
+
+    #[test]
+    fn test_prefilter_gives_identical_results() {
+        let options = RegexOptions::default();
+        let lines = ["foo12 and foo", "no digits", "42 then foo7", "", "ｆｏｏ９ foo٣", "bar 1 foofoo2"];
+
+        assert_eq!(describe_prefilter(r"foo\d+", &options), r#""foo""#);
+        assert_eq!(describe_prefilter(r"\d+", &options), "none");
+
+        // The same patterns with the regex engine's literal prefilter turned off.
+        for pattern in [r"foo\d+", r"\d+", "foo|bar", r"\w+foo"] {
+            let matcher = build_matcher(&[pattern], &options).unwrap();
+            let unfiltered = regex_automata::meta::Regex::builder()
+                .configure(regex_automata::meta::Config::new().auto_prefilter(false))
+                .build(&alternation_pattern(&[pattern], &options))
+                .unwrap();
+
+            for line in lines {
+                let expected: Vec<_> = unfiltered.find_iter(line).map(|m| m.range()).collect();
+                assert_eq!(matcher.find_iter(line).collect::<Vec<_>>(), expected, "{} on {:?}", pattern, line);
+                assert_eq!(matcher.is_match(line), !expected.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_matcher_fixed_strings() {
+        let options = RegexOptions { pattern_type: PatternType::Fixed, ..Default::default() };
+        let matcher = build_matcher(&["f(x).*"], &options).unwrap();
+
+        assert!(matcher.is_match("call f(x).* now"));
+        assert!(!matcher.is_match("call f(x)yz now"));
+    }
+
+    #[test]
+    fn test_build_matcher_fixed_strings_insensitive() {
+        let options = RegexOptions { pattern_type: PatternType::Fixed, insensitive: true, word_regexp: true, ..Default::default() };
+        let matcher = build_matcher(&["a.b"], &options).unwrap();
+
+        assert!(matcher.is_match("x A.B y"));
+        assert!(!matcher.is_match("x aXb y"));
+        assert!(!matcher.is_match("xa.b y"));
+    }
+
+    #[test]
+    fn test_build_matcher_multiple_patterns() {
+        let options = RegexOptions { line_regexp: true, ..Default::default() };
+        let matcher = build_matcher(&["foo", "ba+r"], &options).unwrap();
+
+        assert!(matcher.is_match("foo"));
+        assert!(matcher.is_match("baar"));
+        assert!(!matcher.is_match("foo baar"));
+    }
+
+    #[test]
+    fn test_build_matcher_multiple_basic_patterns() {
+        let options = RegexOptions { pattern_type: PatternType::Basic, ..Default::default() };
+        let matcher = build_matcher(&[r"\(ab\)\{2\}", "f(x)"], &options).unwrap();
+
+        assert!(matcher.is_match("abab"));
+        assert!(matcher.is_match("f(x)"));
+        assert!(!matcher.is_match("ab fx"));
+    }
+
+    #[test]
+    fn test_read_patterns() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "foo")?;
+        writeln!(tmp, "a.c")?;
+
+        let patterns = read_patterns(tmp.path())?;
+        assert_eq!(patterns, vec!["foo", "a.c"]);
+
+        let options = RegexOptions { pattern_type: PatternType::Fixed, insensitive: true, ..Default::default() };
+        let matcher = build_matcher(&patterns, &options).unwrap();
+        assert!(matcher.is_match("A.C"));
+        assert!(!matcher.is_match("abc"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_null_patterns() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        tmp.write_all(b"foo\0hello\nworld\0")?;
+
+        let patterns = read_null_patterns(tmp.path())?;
+        assert_eq!(patterns, vec!["foo", "hello\nworld"]);
+
+        let matcher = build_matcher(&patterns, &RegexOptions { multiline: true, ..Default::default() }).unwrap();
+        assert!(matcher.is_match("say hello\nworld"));
+        assert!(matcher.is_match("food"));
+        assert!(!matcher.is_match("hello world"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_matcher_no_patterns_matches_nothing() {
+        let matcher = build_matcher::<&str>(&[], &RegexOptions::default()).unwrap();
+
+        assert!(!matcher.is_match(""));
+        assert!(!matcher.is_match("anything"));
+    }
+
+    #[test]
+    fn test_build_matcher_many_patterns() {
+        let patterns: Vec<String> = (0..20_000).map(|n| format!("word{}", n)).collect();
+        let options = RegexOptions { pattern_type: PatternType::Fixed, ..Default::default() };
+        let matcher = build_matcher(&patterns, &options).unwrap();
+
+        assert!(matcher.is_match("has word19999 in it"));
+        assert!(!matcher.is_match("has word in it"));
+    }
+
+    #[test]
+    fn test_search_recursive_include() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("src"))?;
+        std::fs::write(dir.path().join("src").join("lib.rs"), "hello\n")?;
+        std::fs::write(dir.path().join("notes.txt"), "hello\n")?;
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions {
+            files_with_matches: true,
+            recursive: true,
+            walk: WalkOptions { include: Some(build_glob_set(&["*.rs"]).unwrap()), ..Default::default() },
+            ..Default::default()
+        };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = search(&[dir.path()], &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{}\n", dir.path().join("src").join("lib.rs").display()));
+        assert_eq!(summary.files_searched, 1);
+        assert!(!summary.had_errors);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_null_file_names() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello")?;
+        let name = tmp.path().display().to_string();
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+
+        let output = |options: SearchOptions| -> std::io::Result<String> {
+            let mut buf: Vec<u8> = Vec::new();
+            search(&[tmp.path()], &regex, &options, &mut buf)?;
+            Ok(String::from_utf8(buf).unwrap())
+        };
+
+        assert_eq!(output(SearchOptions { null: true, files_with_matches: true, ..Default::default() })?, format!("{}\0", name));
+        assert_eq!(output(SearchOptions { null: true, show_header: true, count_matching_lines: true, ..Default::default() })?, format!("{}\01\n", name));
+        assert_eq!(output(SearchOptions { null: true, show_header: true, show_line_numbers: true, ..Default::default() })?, format!("{}\01:hello\n", name));
+        Ok(())
+    }
+
+    #[test]
+    fn test_null_data_records() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        write!(tmp, "a/one.rs\0b/two\nlines.rs\0c/three.txt\0")?;
+
+        let regex = build_regex(r"\.rs$", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { null_data: true, output_eol: OutputEol::Nul, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "a/one.rs\0b/two\nlines.rs\0");
+        assert_eq!(summary.lines, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_color_output() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "a foo b foo")?;
+        let regex = build_regex("foo", &RegexOptions::default()).unwrap();
+        let colors = Colors { file_name: String::new(), ..Default::default() };
+        let options = SearchOptions { show_line_numbers: true, colors: Some(colors), ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\x1b[32m\x1b[K1\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K\
+             a \x1b[01;31m\x1b[Kfoo\x1b[m\x1b[K b \x1b[01;31m\x1b[Kfoo\x1b[m\x1b[K\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlight_matches_within_columns() {
+        let regex = build_regex("ab", &RegexOptions::default()).unwrap();
+        let colors = Colors { matched: String::from("1"), ..Default::default() };
+        let options = SearchOptions { columns: Some(ColumnRange { start: 3, end: None }), colors: Some(colors), ..Default::default() };
+
+        assert_eq!(highlight_matches(&regex, "ab ab", &options), "ab \x1b[1m\x1b[Kab\x1b[m\x1b[K");
+    }
+
+    #[test]
+    fn test_highlight_matches_without_color() {
+        let regex = build_regex("ab", &RegexOptions::default()).unwrap();
+
+        assert!(matches!(highlight_matches(&regex, "ab", &SearchOptions::default()), Cow::Borrowed("ab")));
+    }
+
+    fn binary_output(options: &SearchOptions) -> std::io::Result<String> {
+        let mut tmp = NamedTempFile::new()?;
+        tmp.write_all(b"\x7fELF\0\x01\xff\nsymbol hello\0\nhello again\n")?;
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, options, &mut buf)?;
+
+        Ok(String::from_utf8(buf).unwrap().replace(&tmp.path().display().to_string(), "FILE"))
+    }
+
+    #[test]
+    fn test_binary_file_matches() -> std::io::Result<()> {
+        assert_eq!(binary_output(&SearchOptions::default())?, "Binary file FILE matches\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_offset() -> std::io::Result<()> {
+        let options = SearchOptions { binary_offset: true, ..Default::default() };
+
+        assert_eq!(binary_output(&options)?, "Binary file FILE matches at byte 15 (NUL byte at 4)\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_file_counts_normally() -> std::io::Result<()> {
+        assert_eq!(binary_output(&SearchOptions { count_matching_lines: true, ..Default::default() })?, "2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_files_text() -> std::io::Result<()> {
+        let out = binary_output(&SearchOptions { binary_files: BinaryFiles::Text, ..Default::default() })?;
+
+        assert_eq!(out, "symbol hello\0\nhello again\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_files_without_match() -> std::io::Result<()> {
+        let options = SearchOptions { binary_files: BinaryFiles::WithoutMatch, files_without_match: true, ..Default::default() };
+
+        assert_eq!(binary_output(&options)?, "FILE\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_searched() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        tmp.write_all(b"caf\xe9 hello\r\nnope\n")?;
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        process_file_name(tmp.path(), &regex, &SearchOptions::default(), &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), "caf\u{FFFD} hello\n");
+        Ok(())
+    }
+
+    /// Records how many bytes had been written at each flush.
+    #[derive(Default)]
+    struct FlushLog {
+        written: Vec<u8>,
+        flushed_at: Vec<usize>,
+    }
+
+    impl Write for FlushLog {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed_at.push(self.written.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_line_buffered_flushes_each_line() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello")?;
+        writeln!(tmp, "skip")?;
+        writeln!(tmp, "hello")?;
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+
+        let mut log = FlushLog::default();
+        process_file_name(tmp.path(), &regex, &SearchOptions { line_buffered: true, ..Default::default() }, &mut log)?;
+        assert_eq!(log.flushed_at, vec![6, 12]);
+
+        let mut log = FlushLog::default();
+        process_file_name(tmp.path(), &regex, &SearchOptions::default(), &mut log)?;
+        assert!(log.flushed_at.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_byte_offset() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        write!(tmp, "one\r\ntwo foo\nfoo foo\n")?;
+        let regex = build_regex("foo", &RegexOptions::default()).unwrap();
+
+        let output = |options: SearchOptions| -> std::io::Result<String> {
+            let mut buf: Vec<u8> = Vec::new();
+            process_file_name(tmp.path(), &regex, &options, &mut buf)?;
+            Ok(String::from_utf8(buf).unwrap())
+        };
+
+        let lines = output(SearchOptions { byte_offset: true, show_line_numbers: true, ..Default::default() })?;
+        assert_eq!(lines, "2:5:two foo\n3:13:foo foo\n");
+
+        let matches = output(SearchOptions { byte_offset: true, only_matching: true, ..Default::default() })?;
+        assert_eq!(matches, "9:foo\n13:foo\n17:foo\n");
+        Ok(())
+    }
+
+    #[cfg(feature = "pcre")]
+    #[test]
+    fn test_build_matcher_perl_lookaround_and_backreferences() {
+        let options = RegexOptions { pattern_type: PatternType::Perl, ..Default::default() };
+
+        let matcher = build_matcher(&[r"foo(?=bar)"], &options).unwrap();
+        assert_eq!(matcher.find_iter("foobaz foobar").collect::<Vec<_>>(), vec![Range { start: 7, end: 10 }]);
+
+        let matcher = build_matcher(&[r"\b(\w+) \1\b"], &options).unwrap();
+        assert!(matcher.is_match("it was the the end"));
+        assert!(!matcher.is_match("it was the end"));
+    }
+
+    #[cfg(feature = "pcre")]
+    #[test]
+    fn test_build_matcher_perl_word_regexp() {
+        let options = RegexOptions { pattern_type: PatternType::Perl, word_regexp: true, insensitive: true, ..Default::default() };
+        let matcher = build_matcher(&["@foo"], &options).unwrap();
+
+        assert!(matcher.is_match("mail @FOO now"));
+        assert!(!matcher.is_match("mail x@foo now"));
+    }
+
+    #[test]
+    fn test_search_threads_keeps_file_order() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut expected = String::new();
+        for n in 0..40 {
+            let path = dir.path().join(format!("file{:02}.txt", n));
+            std::fs::write(&path, "x\nhello\n".repeat(n + 1))?;
+            expected.push_str(&format!("{}:{}\n", path.display(), n + 1));
+        }
+        std::fs::create_dir(dir.path().join("sub"))?;
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { threads: 4, recursive: true, show_header: true, count_matching_lines: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = search(&[dir.path()], &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+        assert_eq!(summary.files_searched, 40);
+        assert_eq!(summary.total_matches, (1..=40).sum::<u64>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_heading() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files: Vec<_> = ["a", "b", "c"].iter().map(|name| dir.path().join(name)).collect();
+        std::fs::write(&files[0], "hello\nx\nhello again\n")?;
+        std::fs::write(&files[1], "x\n")?;
+        std::fs::write(&files[2], "hello\n")?;
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let expected = format!("{}\n1:hello\n3:hello again\n\n{}\n1:hello\n", files[0].display(), files[2].display());
+
+        for threads in [1, 4] {
+            let options = SearchOptions { threads, heading: true, show_header: true, show_line_numbers: true, ..Default::default() };
+            let mut buf: Vec<u8> = Vec::new();
+            search(&files, &regex, &options, &mut buf)?;
+            assert_eq!(String::from_utf8(buf).unwrap(), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_threads_reports_errors_in_order() -> std::io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "hello")?;
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { threads: 2, no_messages: true, quiet: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = search(&[Path::new("no/such/file"), tmp.path(), tmp.path()], &regex, &options, &mut buf)?;
+
+        assert!(buf.is_empty());
+        assert!(summary.had_errors);
+        assert_eq!(summary.files_matched, 1);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_search_threads_max_count_stops_reading() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fifo_path = dir.path().join("fifo");
+        let status = std::process::Command::new("mkfifo").arg(&fifo_path).status()?;
+        assert!(status.success(), "mkfifo failed");
+        let other = dir.path().join("other");
+        std::fs::write(&other, "hello 1\nhello 2\n")?;
+
+        // The writer holds the FIFO open after the first line, so reading past
+        // it blocks until the search is done or the wait runs out.
+        let (done_sender, done_receiver) = std::sync::mpsc::channel::<()>();
+        let writer_path = fifo_path.clone();
+        let writer = std::thread::spawn(move || -> std::io::Result<bool> {
+            let mut fifo = std::fs::OpenOptions::new().write(true).open(writer_path)?;
+            writeln!(fifo, "hello 1")?;
+            let stopped = done_receiver.recv_timeout(std::time::Duration::from_secs(10)).is_ok();
+            // The reader is usually gone by now, which makes this fail.
+            let _ = writeln!(fifo, "hello 2");
+            Ok(stopped)
+        });
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { threads: 2, max_count: Some(1), show_header: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = search(&[fifo_path.as_path(), other.as_path()], &regex, &options, &mut buf);
+        let _ = done_sender.send(());
+        let stopped = writer.join().unwrap();
+        summary?;
+
+        assert!(stopped?, "the search read the FIFO past the -m limit");
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{}:hello 1\n{}:hello 1\n", fifo_path.display(), other.display()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_quiet_stops_at_first_matching_file() -> std::io::Result<()> {
+        let mut first = NamedTempFile::new()?;
+        writeln!(first, "nothing")?;
+        let mut second = NamedTempFile::new()?;
+        writeln!(second, "hello")?;
+        writeln!(second, "hello")?;
+        let mut third = NamedTempFile::new()?;
+        writeln!(third, "hello")?;
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { quiet: true, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = search(&[first.path(), second.path(), third.path()], &regex, &options, &mut buf)?;
+
+        assert!(buf.is_empty());
+        assert_eq!(summary.files_searched, 2);
+        assert_eq!(summary.files_matched, 1);
+        assert_eq!(summary.total_lines, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_first_only_stops_at_first_matching_file() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a"), "nothing\n")?;
+        std::fs::write(dir.path().join("b"), "hello\n")?;
+        std::fs::write(dir.path().join("c"), "hello\n")?;
+        // Opening this would be reported as an error.
+        let files = [dir.path().join("a"), dir.path().join("b"), dir.path().join("c"), dir.path().join("missing")];
+
+        let regex = build_regex("hello", &RegexOptions::default()).unwrap();
+        let options = SearchOptions { files_with_matches: true, first_only: true, threads: 4, ..Default::default() };
+
+        let mut buf: Vec<u8> = Vec::new();
+        let summary = search(&files, &regex, &options, &mut buf)?;
+
+        assert_eq!(String::from_utf8(buf).unwrap(), format!("{}\n", files[1].display()));
+        assert_eq!(summary.files_searched, 2);
+        assert!(!summary.had_errors);
+        Ok(())
+    }
+
+    /// Should write tests
+    #[test]
+    fn test_should_write_line_match_and_no_invert_without_count() -> Result<()> {
+        let invert = false;
+        let is_match = true;
+        let count_matching_lines = false;
+
+        assert_eq!(should_write_line(is_match, invert, count_matching_lines), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_write_line_match_and_invert_without_count() -> Result<()> {
+        let invert = true;
+        let is_match = true;
+        let count_matching_lines = false;
+
+        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_write_line_no_match_and_invert_without_count() -> Result<()> {
+        let invert = false;
+        let is_match = true;
+        let count_matching_lines = false;
+
+        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_write_line_no_match_and_no_invert_without_count() -> Result<()> {
+        let invert = false;
+        let is_match = false;
+        let count_matching_lines = false;
+
+        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_write_line_match_and_no_invert_with_count() -> Result<()> {
+        let invert = false;
+        let is_match = true;
+        let count_matching_lines = true;
+
+        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_write_line_match_and_invert_with_count() -> Result<()> {
+        let invert = true;
+        let is_match = true;
+        let count_matching_lines = true;
+
+        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_write_line_no_match_and_invert_with_count() -> Result<()> {
+        let invert = false;
+        let is_match = true;
+        let count_matching_lines = true;
+
+        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_write_line_no_match_and_no_invert_with_count() -> Result<()> {
+        let invert = false;
+        let is_match = false;
+        let count_matching_lines = true;
+
+        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+
+        Ok(())
+    }
+}
@@ -1,15 +1,25 @@
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{self, Write, BufRead, BufReader};
-use std::path::Path;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread;
+use std::time::Instant;
 
 use anyhow::Result;
-use clap::{ArgAction, Parser as ClapParser};
-use log::{info};
-use regex::{Regex, RegexBuilder};
+use clap::builder::PossibleValuesParser;
+use clap::{ArgAction, CommandFactory, Parser as ClapParser, Subcommand};
+use clap_complete::Shell;
+use log::{info, LevelFilter};
+use globset::GlobSet;
+use ignore::gitignore::GitignoreBuilder;
+use ignore::types::Types;
+use regex::Regex;
 
+use rusty_grep::{config_path, read_config, build_glob_set, build_overrides, build_types, default_type_names, build_matcher, has_uppercase_literal, list_files, parse_size, read_null_patterns, read_patterns, search, BinaryFiles, ColorChoice, Colors, ColumnRange, Encoding, Matcher, OutputEol, PathFormat, PathGlob, PatternType, RegexOptions, SearchOptions, SearchSummary, SortBy, WalkOptions, DEFAULT_CONTEXT_LINE_REGEX, STDIN_FILE_NAME};
 
 #[derive(ClapParser, Default)]
-#[command(version, about, long_about = None, disable_help_flag=true)]
+#[command(version, about, long_about = None, disable_help_flag=true, args_override_self = true, subcommand_negates_reqs = true, disable_help_subcommand = true)]
 pub struct Cli {
     /// Show header
     #[arg(short='H', long, value_name = "HEADER")]
@@ -23,6 +33,47 @@ pub struct Cli {
     #[arg(short, long, value_name = "CASE INSENSITIVE")]
     pub insensitive: bool,
 
+    /// Ignore case unless a pattern contains an uppercase letter
+    #[arg(short = 'S', long, value_name = "SMART CASE")]
+    pub smart_case: bool,
+
+    /// Regex dialect of the pattern
+    #[arg(long, value_enum, value_name = "TYPE", default_value_t = PatternType::Extended)]
+    pub pattern_type: PatternType,
+
+    /// Treat the pattern as a POSIX basic regex (same as --pattern-type=basic)
+    #[arg(short = 'G', long, value_name = "BASIC REGEXP", conflicts_with_all = ["extended_regexp", "fixed_strings"])]
+    pub basic_regexp: bool,
+
+    /// Treat the pattern as an extended regex (same as --pattern-type=extended)
+    #[arg(short = 'E', long, value_name = "EXTENDED REGEXP", conflicts_with = "fixed_strings")]
+    pub extended_regexp: bool,
+
+    /// Treat the pattern as a literal string (same as --pattern-type=fixed)
+    #[arg(short = 'F', long, value_name = "FIXED STRINGS")]
+    pub fixed_strings: bool,
+
+    /// Treat the pattern as Perl-compatible, with lookaround and backreferences (same as --pattern-type=perl)
+    #[cfg(feature = "pcre")]
+    #[arg(short = 'P', long, visible_alias = "pcre2", value_name = "PERL REGEXP", conflicts_with_all = ["fixed_strings", "basic_regexp", "extended_regexp"])]
+    pub perl_regexp: bool,
+
+    /// Only match at the start of a line
+    #[arg(long, value_name = "STARTS WITH")]
+    pub starts_with: bool,
+
+    /// Only match at the end of a line
+    #[arg(long, value_name = "ENDS WITH")]
+    pub ends_with: bool,
+
+    /// Only match whole words, bounded by non-word characters or the line edges
+    #[arg(short = 'w', long, value_name = "WORD REGEXP")]
+    pub word_regexp: bool,
+
+    /// Only match when the pattern matches the whole line
+    #[arg(short = 'x', long, value_name = "LINE REGEXP")]
+    pub line_regexp: bool,
+
     /// Invert match
     #[arg(short='v', long, value_name = "INVERT MATCH")]
     pub invert_match: bool,
@@ -31,406 +82,725 @@ pub struct Cli {
     #[arg(short='n', long, value_name = "LINE NUMBERS")]
     pub show_line_numbers: bool,
 
+    /// Prefix each output line, or each match with -o, with its byte offset in the file
+    #[arg(short = 'b', long, value_name = "BYTE OFFSET")]
+    pub byte_offset: bool,
+
     /// Count matching lines
     #[arg(short, long, value_name = "COUNT MATCHING LINES")]
     pub count_matching_lines: bool,
 
-    /// Regex to search for
-    #[arg(value_name = "REGEX", required = true)]
-    pub regex: String,
+    /// Like --count, but only for files with at least one matching line (now the default)
+    #[arg(long, value_name = "COUNT NONZERO")]
+    pub count_nonzero: bool,
 
-    /// One or more files to check
-    #[arg(value_name = "FILE", required = true)]
-    pub file_names: Vec<String>,
+    /// Print the counts of files with no matches too, as a count of 0
+    #[arg(long, value_name = "INCLUDE ZERO", overrides_with = "count_nonzero")]
+    pub include_zero: bool,
 
-    /// Manually restore --help
-    #[arg(long = "help", action = ArgAction::Help, help = "Print help information")]
-    help: Option<bool>,
-}
+    /// Print the total number of matched bytes per file
+    #[arg(long, value_name = "COUNT BYTES", conflicts_with_all = ["count_matching_lines", "count_nonzero"])]
+    pub count_bytes: bool,
 
-fn main() -> Result<()> {
-    env_logger::init();
+    /// Print the number of matches per file, counting every match on a line rather than the lines
+    #[arg(long, value_name = "COUNT MATCHES", conflicts_with_all = ["count_matching_lines", "count_nonzero", "count_bytes"])]
+    pub count_matches: bool,
 
-    info!("Rusty Curl");
+    /// Print the number of lines per file that -A, -B and -C would print, counting overlapping context once
+    #[arg(long, value_name = "COUNT WITH CONTEXT", conflicts_with_all = ["count_matching_lines", "count_nonzero", "count_bytes", "count_matches", "json"])]
+    pub count_with_context: bool,
 
-    let cli = Cli::parse();
+    /// Print only the names of files with a matching line
+    #[arg(short = 'l', long, value_name = "FILES WITH MATCHES")]
+    pub files_with_matches: bool,
 
-    let regex = build_regex(&cli.regex, cli.insensitive)?;
-    let show_header = cli.show_header || cli.file_names.len() > 1;
+    /// With -l, stop searching at the first matching file and print only its name
+    #[arg(long, value_name = "FIRST ONLY", requires = "files_with_matches")]
+    pub first_only: bool,
 
+    /// Print only the names of files without a matching line
+    #[arg(short = 'L', long, value_name = "FILES WITHOUT MATCH", conflicts_with = "files_with_matches")]
+    pub files_without_match: bool,
 
-    for file_name in cli.file_names.iter() {
-        process_file_name(&file_name, &regex, show_header, cli.no_header, cli.invert_match, cli.show_line_numbers, cli.count_matching_lines, io::stdout())?;
-    }
+    /// Print nothing; exit 0 as soon as any line matches
+    #[arg(short = 'q', long, value_name = "QUIET")]
+    pub quiet: bool,
 
-    Ok(())
-}
+    /// Don't report nonexistent or unreadable files (the exit status still does)
+    #[arg(short = 's', long, value_name = "NO MESSAGES")]
+    pub no_messages: bool,
 
-fn build_regex(regex_str: &str, insensitive: bool) -> Result<Regex, regex::Error> {
-    RegexBuilder::new(regex_str)
-        .case_insensitive(insensitive)
-        .build()
-}
+    /// Search the files under each directory argument, or under "." when none are given
+    #[arg(short = 'r', long, value_name = "RECURSIVE")]
+    pub recursive: bool,
 
-/// Returns `Ok(())` on success, writes matches to `out`.
-fn process_file_name<P: AsRef<Path>, W: Write>(
-    file_name: P,
-    regex: &Regex,
-    show_header: bool,
-    no_header: bool,
-    invert_match: bool,
-    show_line_numbers: bool,
-    count_matching_lines: bool,
-    mut out: W,
-) -> io::Result<()> {
-    let file_path = file_name.as_ref();
-    let file_name_str = file_path.to_str().unwrap_or_default(); // safe fallback
-
-    let reader = open_reader(file_name.as_ref())?;
-    let mut line_number: u32 = 0;
-    let mut matching_lines: u32 = 0;
-
-    for line_result in reader.lines() {
-        line_number += 1;
-        let line = line_result?;
-        let is_match = regex.is_match(&line);
-
-        if is_match {
-            matching_lines += 1;
-        }
+    /// When recursing, only search files whose path below the searched directory matches GLOB, or with a leading ! skip them; may be repeated
+    #[arg(short = 'g', long, value_name = "GLOB")]
+    pub glob: Vec<String>,
 
-        if should_write_line(is_match, invert_match, count_matching_lines) {
-            let prefix = build_prefix(file_name_str, show_header, no_header, show_line_numbers, line_number);
-            writeln!(out, "{}{}", prefix, line)?;
-        }
-    }
+    /// Like --glob, but ignoring case
+    #[arg(long, value_name = "GLOB")]
+    pub iglob: Vec<String>,
 
-    if count_matching_lines {
-        if show_header {
-            writeln!(out, "{}:{}", file_name_str, matching_lines)?;
-        }
-        else {
-            writeln!(out, "{}", matching_lines)?;
-        }
-    }
+    /// When recursing, only search files whose name matches GLOB; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
 
-    Ok(())
-}
+    /// When recursing, skip files whose name matches GLOB; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
 
-fn should_write_line(is_match: bool, invert_match: bool, count_matching_lines: bool) -> bool {
-    is_match != invert_match && !count_matching_lines
-}
+    /// When recursing, skip directories whose name matches GLOB; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub exclude_dir: Vec<String>,
 
-fn build_prefix(file_name: &str, show_header: bool, no_header: bool, show_line_numbers: bool, line_number: u32) -> String {
-    let mut prefix = String::new();
+    /// When recursing, only descend into directories whose name matches GLOB, and everything under them; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub include_dir: Vec<String>,
 
-    if show_header && !no_header {
-        prefix.push_str(&format!("{}:", file_name));
-    }
+    /// When recursing, also search files ignored by .gitignore, .ignore or similar files; --ignore-file rules still apply
+    #[arg(long, value_name = "NO IGNORE")]
+    pub no_ignore: bool,
 
-    if show_line_numbers {
-        prefix.push_str(&format!("{}:", line_number));
-    }
+    /// When recursing, disregard .gitignore and .git/info/exclude
+    #[arg(long, value_name = "NO IGNORE VCS")]
+    pub no_ignore_vcs: bool,
 
-    prefix
-}
+    /// When recursing, disregard ignore files in directories above the one searched
+    #[arg(long, value_name = "NO IGNORE PARENT")]
+    pub no_ignore_parent: bool,
 
-fn open_reader<P: AsRef<Path>>(path: P) -> io::Result<BufReader<File>> {
-    let file = File::open(path)?;
-    Ok(BufReader::new(file))
-}
+    /// When recursing, disregard .ignore files
+    #[arg(long, value_name = "NO IGNORE DOT")]
+    pub no_ignore_dot: bool,
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::{Write, BufRead};
-    use tempfile::NamedTempFile;
+    /// When recursing, also skip files matched by the gitignore-style rules in PATH; may be repeated
+    #[arg(long, value_name = "PATH")]
+    pub ignore_file: Vec<PathBuf>,
 
-    #[test]
-    fn test_build_regex_without_insensitive() -> Result<()> {
-        let regex = build_regex("hello", false)?;
+    /// When recursing, also search hidden files and directories
+    #[arg(long, value_name = "HIDDEN")]
+    pub hidden: bool,
 
-        assert_eq!(regex.is_match("some text HELLO more text"), false);
+    /// When recursing, only search files of type TYPE (e.g. rust, py, md); may be repeated
+    #[arg(short = 't', long = "type", value_name = "TYPE")]
+    pub file_type: Vec<String>,
 
-        Ok(())
-    }
+    /// When recursing, skip files of type TYPE; may be repeated
+    #[arg(short = 'T', long = "type-not", value_name = "TYPE")]
+    pub file_type_not: Vec<String>,
 
-    #[test]
-    fn test_build_regex_with_insensitive() -> Result<()> {
-        let regex = build_regex("hello", true)?;
+    /// Define a file type for --type, e.g. 'proto:*.proto'; may be repeated
+    #[arg(long, value_name = "NAME:GLOB")]
+    pub type_add: Vec<String>,
 
-        assert_eq!(regex.is_match("some text HELLO more text"), true);
+    /// When recursing, skip files larger than SIZE (e.g. 512K, 10M, 1G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_filesize: Option<u64>,
 
-        Ok(())
-    }
+    /// When recursing, descend at most NUM directories deep; 1 searches only the files directly inside
+    #[arg(long, value_name = "NUM")]
+    pub max_depth: Option<usize>,
 
-    #[test]
-    fn test_build_prefix_with_header_without_line_numbers() -> Result<()> {
-        let prefix_with_header = build_prefix("some_file", true, false, false, 22);
+    /// When recursing, follow symbolic links instead of skipping them
+    #[arg(long, value_name = "FOLLOW")]
+    pub follow: bool,
 
-        assert_eq!(prefix_with_header, "some_file:");
+    /// When recursing, search files in this order
+    #[arg(long, value_enum, value_name = "SORTBY", conflicts_with = "sortr")]
+    pub sort: Option<SortBy>,
 
-        Ok(())
-    }
+    /// When recursing, search files in the reverse of this order
+    #[arg(long, value_enum, value_name = "SORTBY")]
+    pub sortr: Option<SortBy>,
 
-    #[test]
-    fn test_build_prefix_without_header_without_line_numbers() -> Result<()> {
-        let prefix_with_header = build_prefix("some_file", false, false, false, 22);
+    /// Search up to NUM files at once (default: the number of CPUs)
+    #[arg(short = 'j', long, value_name = "NUM")]
+    pub threads: Option<usize>,
 
-        assert_eq!(prefix_with_header, "");
+    /// Follow file names with a NUL byte instead of ':' or a newline, for xargs -0
+    #[arg(short = 'Z', long, value_name = "NULL")]
+    pub null: bool,
 
-        Ok(())
-    }
+    /// Input and output records end with a NUL byte instead of a newline
+    #[arg(short = 'z', long, value_name = "NULL DATA")]
+    pub null_data: bool,
 
-    #[test]
-    fn test_build_prefix_with_header_with_line_numbers() -> Result<()> {
-        let prefix_with_header = build_prefix("some_file", true, false, true, 22);
+    /// Stop reading a file after NUM selected lines
+    #[arg(short = 'm', long, value_name = "NUM")]
+    pub max_count: Option<u64>,
 
-        assert_eq!(prefix_with_header, "some_file:22:");
+    /// Print only the matched parts of each line, one per output line
+    #[arg(short = 'o', long, value_name = "ONLY MATCHING")]
+    pub only_matching: bool,
 
-        Ok(())
-    }
+    /// With -o, print the matches on each line together on one output line, separated by SEP
+    #[arg(long, value_name = "SEP", requires = "only_matching")]
+    pub join_matches: Option<String>,
 
-    #[test]
-    fn test_build_prefix_without_header_with_line_numbers() -> Result<()> {
-        let prefix_with_header = build_prefix("some_file", false, false, true, 22);
+    /// Print NUM lines of trailing context after matching lines
+    #[arg(short = 'A', long, value_name = "NUM")]
+    pub after_context: Option<usize>,
 
-        assert_eq!(prefix_with_header, "22:");
+    /// Print NUM lines of leading context before matching lines
+    #[arg(short = 'B', long, value_name = "NUM")]
+    pub before_context: Option<usize>,
 
-        Ok(())
-    }
+    /// Print NUM lines of context around matching lines
+    #[arg(short = 'C', long, value_name = "NUM")]
+    pub context: Option<usize>,
 
-    #[test]
-    fn test_open_reader_reads_file() -> io::Result<()> {
-        // 1. Create a temporary file
-        let mut tmpfile = NamedTempFile::new()?;
+    /// Print SEP instead of "--" between non-adjacent groups of context
+    #[arg(long, value_name = "SEP", allow_hyphen_values = true)]
+    pub group_separator: Option<String>,
 
-        // 2. Write some content to it
-        writeln!(tmpfile, "hello world")?;
-        writeln!(tmpfile, "goodbye world")?;
+    /// Print nothing between non-adjacent groups of context
+    #[arg(long, value_name = "NO GROUP SEPARATOR", overrides_with = "group_separator")]
+    pub no_group_separator: bool,
 
-        // 3. Re-open the file through your function
-        let reader = open_reader(tmpfile.path())?;
+    /// Match whole words using Unicode word segmentation
+    #[cfg(feature = "unicode-words")]
+    #[arg(long, value_name = "MATCH WHOLE WORDS")]
+    pub match_whole_words: bool,
 
-        // 4. Collect the lines and verify the content
-        let lines: Vec<_> = reader.lines().collect::<Result<_, _>>()?;
-        assert_eq!(lines, vec!["hello world", "goodbye world"]);
+    /// Also report files whose name matches the regex
+    #[arg(long, value_name = "MATCH FILENAMES")]
+    pub match_filenames: bool,
 
-        Ok(())
-    }
+    /// Keep reading as the file grows, like tail -f (only one file may be given; Ctrl-C to stop)
+    #[arg(long, value_name = "TAIL")]
+    pub tail: bool,
 
-    #[test]
-    fn test_open_reader_nonexistent_file() {
-        // 1. Pick a definitely-nonexistent file path
-        let bogus_path = "this_file_should_not_exist_12345.txt";
+    /// Only match within byte columns START:END of each line (1-based, inclusive)
+    #[arg(long, value_name = "START:END")]
+    pub columns: Option<ColumnRange>,
 
-        // 2. Call your function
-        let result = open_reader(bogus_path);
+    /// Write results to FILE instead of stdout
+    #[arg(short = 'O', long, value_name = "FILE")]
+    pub output_file: Option<String>,
 
-        // 3. Verify it failed
-        assert!(result.is_err(), "Expected error for nonexistent file, got Ok");
+    /// Write results as JSON Lines messages in ripgrep's --json format
+    #[arg(long, value_name = "JSON", conflicts_with_all = ["count_matching_lines", "count_nonzero", "count_bytes", "count_matches", "files_with_matches", "files_without_match", "only_matching", "sum_matches", "match_filenames"])]
+    pub json: bool,
 
-        // 4. Optionally: check the specific error kind
-        if let Err(err) = result {
-            assert_eq!(err.kind(), io::ErrorKind::NotFound);
-        }
-    }
+    /// Print each match rewritten by TEMPLATE, where $1 or ${name} is a capture group; files are left untouched. With color, the old text is shown struck out before it
+    #[arg(long, value_name = "TEMPLATE", allow_hyphen_values = true, conflicts_with = "json")]
+    pub replace: Option<String>,
 
-    #[test]
-    fn test_process_file_name_matches_lines() -> std::io::Result<()> {
-        let mut tmp = NamedTempFile::new()?;
-        writeln!(tmp, "hello")?;
-        writeln!(tmp, "world")?;
-        writeln!(tmp, "HELLO")?;
-        // Flush/close the file handle so reads see it
-        let path = tmp.path().to_path_buf();
-
-        let regex = build_regex("hello", false).unwrap(); // case-sensitive
-
-        let mut buf: Vec<u8> = Vec::new();
-        process_file_name(&path, &regex, false, false, false, false, false, &mut buf)?;
-
-        let out = String::from_utf8(buf).expect("output was not valid UTF-8");
-        assert!(out.contains("hello"));
-        assert!(!out.contains("world"));
-        // "HELLO" only matches if case-insensitive; here it should not.
-        assert!(!out.contains("HELLO"));
-        Ok(())
-    }
+    /// Let matches span lines, e.g. 'fn foo\(\n'; every line a match touches is printed
+    #[arg(short = 'U', long, value_name = "MULTILINE", conflicts_with_all = ["tail", "columns", "replace"])]
+    pub multiline: bool,
 
-    #[test]
-    fn test_process_file_name_matches_lines_count_matching() -> std::io::Result<()> {
-        let mut tmp = NamedTempFile::new()?;
-        writeln!(tmp, "hello")?;
-        writeln!(tmp, "world")?;
-        writeln!(tmp, "HELLO")?;
-        // Flush/close the file handle so reads see it
-        let path = tmp.path().to_path_buf();
-
-        let regex = build_regex("hello", false).unwrap(); // case-sensitive
-
-        let mut buf: Vec<u8> = Vec::new();
-        process_file_name(&path, &regex, false, false, false, false, true, &mut buf)?;
-
-        let out = String::from_utf8(buf).expect("output was not valid UTF-8");
-        println!("out = {}", out);
-        assert!(!out.contains("hello"));
-        assert!(!out.contains("world"));
-        // "HELLO" only matches if case-insensitive; here it should not.
-        assert!(!out.contains("HELLO"));
-        assert!(out.contains("1"));
-        Ok(())
-    }
+    /// Search compressed files (gzip, bzip2, xz, lz4, zstd) by their contents, using the installed tools
+    #[arg(long, value_name = "SEARCH ZIP")]
+    pub search_zip: bool,
 
-    #[test]
-    fn test_process_file_name_matches_lines_count_matching_with_header() -> std::io::Result<()> {
-        let mut tmp = NamedTempFile::new()?;
-        writeln!(tmp, "hello")?;
-        writeln!(tmp, "world")?;
-        writeln!(tmp, "HELLO")?;
-        // Flush/close the file handle so reads see it
-        let path = tmp.path().to_path_buf();
+    /// Search the output of COMMAND, run with each file's path as its argument and the file as its input
+    #[arg(long, value_name = "COMMAND")]
+    pub pre: Option<String>,
 
-        let regex = build_regex("hello", false).unwrap(); // case-sensitive
+    /// Read input in this encoding instead of UTF-8: any WHATWG label, such as utf-16le, latin1 or shift_jis
+    #[arg(long, value_name = "ENCODING")]
+    pub encoding: Option<Encoding>,
 
-        let mut buf: Vec<u8> = Vec::new();
-        process_file_name(&path, &regex, true, false, false, false, true, &mut buf)?;
+    /// Memory-map files instead of reading them through a buffer, which can be faster for large local files
+    #[arg(long, value_name = "MMAP")]
+    pub mmap: bool,
 
-        let out = String::from_utf8(buf).expect("output was not valid UTF-8");
+    /// Memory-map files of at least SIZE (e.g. 512K, 10M) and read smaller ones through a buffer
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub mmap_threshold: Option<u64>,
 
-        let filename = path.to_str().unwrap();
+    /// Never memory-map files, overriding --mmap and --mmap-threshold, for filesystems where mapping misbehaves
+    #[arg(long, value_name = "NO_MMAP")]
+    pub no_mmap: bool,
 
-        println!("out = {}", out);
-        assert!(!out.contains("hello"));
-        assert!(!out.contains("world"));
-        // "HELLO" only matches if case-insensitive; here it should not.
-        assert!(!out.contains("HELLO"));
-        assert!(out.contains("1"));
-        assert!(out.contains(&format!("{}:1", filename)));
-        Ok(())
-    }
+    /// Print file:line:column:text for every match, for Vim's quickfix list
+    #[arg(long, value_name = "VIMGREP", conflicts_with = "json")]
+    pub vimgrep: bool,
 
-    #[test]
-    fn test_process_file_name_with_header() -> std::io::Result<()> {
-        let mut tmp = NamedTempFile::new()?;
-        writeln!(tmp, "foo")?;
-        writeln!(tmp, "bar")?;
-        let path = tmp.path().to_path_buf();
+    /// Print every line, highlighting matches instead of filtering on them
+    #[arg(long, value_name = "PASSTHRU", conflicts_with_all = ["only_matching", "invert_match"])]
+    pub passthru: bool,
 
-        let regex = build_regex("foo", false).unwrap();
+    /// Print the 1-based column of the first match on each line, after the line number
+    #[arg(long, value_name = "COLUMN")]
+    pub column: bool,
 
-        let mut buf: Vec<u8> = Vec::new();
-        process_file_name(&path, &regex, true, false, false, false, false, &mut buf)?; // show_header = true
+    /// Print each file name once above its matching lines, with a blank line between files (default on a terminal)
+    #[arg(long, value_name = "HEADING", overrides_with = "no_heading")]
+    pub heading: bool,
 
-        let out = String::from_utf8(buf).unwrap();
-        // Expect the prefix (filename:) and the matched line
-        let filename = path.to_str().unwrap();
-        assert!(out.contains(&format!("{}:foo", filename)));
-        Ok(())
-    }
+    /// Print the file name on every line, even on a terminal
+    #[arg(long, value_name = "NO HEADING", overrides_with = "heading")]
+    pub no_heading: bool,
+
+    /// List the files that would be searched, after all filtering, without searching them
+    #[arg(long, visible_alias = "list-files-only", value_name = "FILES")]
+    pub files: bool,
 
-    #[test]
-    fn test_process_file_name_no_matches_outputs_nothing() -> std::io::Result<()> {
-        let mut tmp = NamedTempFile::new()?;
-        writeln!(tmp, "alpha")?;
-        writeln!(tmp, "beta")?;
-        let path = tmp.path().to_path_buf();
+    /// Print totals and the elapsed time after the results (--json includes them already)
+    #[arg(long, value_name = "STATS")]
+    pub stats: bool,
 
-        let regex = build_regex("zzz", false).unwrap();
+    /// Print a JSON summary of the run to stderr when done
+    #[arg(long, value_name = "SUMMARY JSON")]
+    pub summary_json: bool,
+
+    /// How to handle files containing NUL bytes
+    #[arg(long, value_enum, value_name = "TYPE", default_value_t = BinaryFiles::Binary)]
+    pub binary_files: BinaryFiles,
+
+    /// Say at which byte a binary file's first match and first NUL byte are, instead of just that it matches
+    #[arg(long, value_name = "BINARY_OFFSET")]
+    pub binary_offset: bool,
+
+    /// Show NAME instead of "(standard input)" for input read from stdin
+    #[arg(long, value_name = "NAME")]
+    pub label: Option<String>,
+
+    /// Print file paths relative to the search root they were found under or as absolute paths, instead of as given
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub path_format: Option<PathFormat>,
+
+    /// Flush output after every line, for following a growing input through a pipe
+    #[arg(long, value_name = "LINE BUFFERED")]
+    pub line_buffered: bool,
+
+    /// Highlight matches, file names and line numbers; a bare --color means auto, as in GNU grep
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto, num_args = 0..=1, require_equals = true, default_missing_value = "auto")]
+    pub color: ColorChoice,
+
+    /// Line terminator for output
+    #[arg(long, value_enum, value_name = "EOL", default_value_t = OutputEol::Lf)]
+    pub output_eol: OutputEol,
+
+    /// Print only the total number of matches across all files
+    #[arg(long = "sum", value_name = "SUM MATCHES")]
+    pub sum_matches: bool,
+
+    /// Print the last line matching REGEX (default: Rust items) before each match
+    #[arg(long, value_name = "REGEX", num_args = 0..=1, require_equals = true, default_missing_value = DEFAULT_CONTEXT_LINE_REGEX)]
+    pub show_context_line: Option<String>,
+
+    /// Measure matching throughput instead of printing matches
+    #[arg(long, hide = true)]
+    pub benchmark: bool,
+
+    /// Pattern to search for; may be repeated, and a line matches if any pattern does
+    #[arg(short = 'e', long = "regexp", value_name = "PATTERN")]
+    pub patterns: Vec<String>,
+
+    /// Read patterns from FILE, one per line; may be repeated
+    #[arg(short = 'f', long = "file", value_name = "FILE")]
+    pub pattern_files: Vec<String>,
+
+    /// Split -f files on NUL bytes instead of newlines, so patterns can contain newlines
+    #[arg(long, value_name = "PATTERNS NULL")]
+    pub patterns_null: bool,
+
+    /// Regex to search for; when -e or -f is given this is the first FILE instead
+    #[arg(value_name = "REGEX", required_unless_present_any = ["patterns", "pattern_files", "files"])]
+    pub regex: Option<String>,
+
+    /// Files to check; with none, or with "-", standard input is read
+    #[arg(value_name = "FILE")]
+    pub file_names: Vec<String>,
 
-        let mut buf: Vec<u8> = Vec::new();
-        process_file_name(&path, &regex, false, false, false, false, false, &mut buf)?;
+    /// Explain the search on stderr: the compiled pattern, its literal prefilter, how each file is read and which files are skipped and why
+    #[arg(long, value_name = "DEBUG")]
+    pub debug: bool,
 
-        assert!(buf.is_empty());
-        Ok(())
+    /// Ignore the config file ($RUSTY_GREP_CONFIG or ~/.config/rusty_grep/config)
+    #[arg(long, value_name = "NO CONFIG")]
+    pub no_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Manually restore --help
+    #[arg(long = "help", action = ArgAction::Help, help = "Print help information")]
+    help: Option<bool>,
+}
+
+// Things to do instead of searching. A doc comment here would replace the
+// program's own about text in --help.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print a completion script for SHELL to standard output
+    Completions {
+        #[arg(value_enum, value_name = "SHELL")]
+        shell: Shell,
+    },
+    /// Print a roff man page for rusty_grep to standard output
+    Man,
+}
+
+fn main() -> ExitCode {
+    let (args, config) = args_with_config();
+    let cli = Cli::parse_from(args);
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if cli.debug {
+        logger.filter_level(LevelFilter::Debug);
+    }
+    logger.init();
+
+    info!("Rusty Curl");
+    if let Some(config) = config {
+        info!("read default arguments from {}", config.display());
     }
 
-    /// Should write tests
-    #[test]
-    fn test_should_write_line_match_and_no_invert_without_count() -> Result<()> {
-        let invert = false;
-        let is_match = true;
-        let count_matching_lines = false;
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            // Bad patterns and globs are usage errors, which grep reports with status 2.
+            eprintln!("rusty_grep: {:#}", err);
+            ExitCode::from(2)
+        }
+    }
+}
 
-        assert_eq!(should_write_line(is_match, invert, count_matching_lines), true);
+/// The command line with the config file's arguments put in front, so
+/// anything given on the command line overrides them, along with the file
+/// they came from. `--no-config` skips the file, and one that can't be read
+/// only gets a warning.
+fn args_with_config() -> (Vec<OsString>, Option<PathBuf>) {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
 
-        Ok(())
+    let options = args.iter().skip(1).take_while(|arg| *arg != "--");
+    if options.clone().any(|arg| arg == "--no-config") {
+        return (args, None);
     }
 
-    #[test]
-    fn test_should_write_line_match_and_invert_without_count() -> Result<()> {
-        let invert = true;
-        let is_match = true;
-        let count_matching_lines = false;
+    let Some((path, explicit)) = config_path() else {
+        return (args, None);
+    };
 
-        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+    match read_config(&path) {
+        Ok(config_args) => {
+            args.splice(1..1, config_args);
+            (args, Some(path))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound && !explicit => (args, None),
+        Err(err) => {
+            eprintln!("rusty_grep: {}: {}", path.display(), err);
+            (args, None)
+        }
+    }
+}
+
+fn run(mut cli: Cli) -> Result<ExitCode> {
+    match cli.command {
+        Some(Command::Completions { shell }) => {
+            write_completions(shell, &mut io::stdout())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Man) => {
+            clap_mangen::Man::new(Cli::command()).render(&mut io::stdout())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
 
-        Ok(())
+    // Like grep, once patterns come from -e or -f every positional argument is a
+    // file. --files takes no pattern, so the same goes for it.
+    if let Some(regex) = cli.regex.take() {
+        if cli.patterns.is_empty() && cli.pattern_files.is_empty() && !cli.files {
+            cli.patterns.push(regex);
+        }
+        else {
+            cli.file_names.insert(0, regex);
+        }
     }
 
-    #[test]
-    fn test_should_write_line_no_match_and_invert_without_count() -> Result<()> {
-        let invert = false;
-        let is_match = true;
-        let count_matching_lines = false;
+    for pattern_file in &cli.pattern_files {
+        let patterns = if cli.patterns_null { read_null_patterns(pattern_file) } else { read_patterns(pattern_file) };
+        match patterns {
+            Ok(patterns) => cli.patterns.extend(patterns),
+            Err(err) => {
+                eprintln!("rusty_grep: {}: {}", pattern_file, err);
+                return Ok(ExitCode::from(2));
+            }
+        }
+    }
 
-        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), true);
+    // Listing files is only useful for what's under a directory.
+    cli.recursive |= cli.files;
 
-        Ok(())
+    if cli.no_mmap {
+        cli.mmap = false;
+        cli.mmap_threshold = None;
+    }
+    else if (cli.mmap || cli.mmap_threshold.is_some()) && !cfg!(any(unix, windows)) {
+        eprintln!("rusty_grep: memory mapping isn't supported on this platform; reading files through a buffer");
+        cli.mmap = false;
+        cli.mmap_threshold = None;
     }
 
-    #[test]
-    fn test_should_write_line_no_match_and_no_invert_without_count() -> Result<()> {
-        let invert = false;
-        let is_match = false;
-        let count_matching_lines = false;
+    if cli.file_names.is_empty() {
+        let default_file = if cli.recursive { "." } else { STDIN_FILE_NAME };
+        cli.file_names.push(default_file.to_string());
+    }
 
-        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+    // Following a file never reaches its end, so the files after it would never be read.
+    if cli.tail && cli.file_names.len() > 1 {
+        eprintln!("rusty_grep: --tail follows a single file, but {} were given", cli.file_names.len());
+        return Ok(ExitCode::from(2));
+    }
 
-        Ok(())
+    let pattern_type = pattern_type(&cli);
+    let smart_insensitive = cli.smart_case && !cli.patterns.iter().any(|pattern| has_uppercase_literal(pattern, pattern_type));
+    let regex_options = RegexOptions {
+        insensitive: cli.insensitive || smart_insensitive,
+        pattern_type,
+        starts_with: cli.starts_with,
+        ends_with: cli.ends_with,
+        word_regexp: cli.word_regexp,
+        line_regexp: cli.line_regexp,
+        multiline: cli.multiline,
+    };
+    let matcher = build_matcher(&cli.patterns, &regex_options)?;
+    let context_line_regex = cli.show_context_line.as_deref().map(Regex::new).transpose()?;
+    let options = SearchOptions {
+        show_header: cli.show_header || cli.file_names.len() > 1 || cli.recursive || cli.vimgrep,
+        no_header: cli.no_header,
+        invert_match: cli.invert_match,
+        show_line_numbers: cli.show_line_numbers || cli.vimgrep,
+        count_matching_lines: cli.count_matching_lines || cli.count_nonzero,
+        #[cfg(feature = "unicode-words")]
+        match_whole_words: cli.match_whole_words,
+        context_line_regex,
+        sum_matches: cli.sum_matches,
+        match_filenames: cli.match_filenames,
+        tail: cli.tail,
+        output_eol: if cli.null_data { OutputEol::Nul } else { cli.output_eol },
+        columns: cli.columns,
+        count_nonzero: !cli.include_zero,
+        count_bytes: cli.count_bytes,
+        count_matches: cli.count_matches,
+        count_with_context: cli.count_with_context,
+        before_context: cli.before_context.or(cli.context).unwrap_or(0),
+        after_context: cli.after_context.or(cli.context).unwrap_or(0),
+        only_matching: cli.only_matching,
+        join_matches: cli.join_matches,
+        files_with_matches: cli.files_with_matches,
+        files_without_match: cli.files_without_match,
+        quiet: cli.quiet,
+        first_only: cli.first_only,
+        max_count: cli.max_count,
+        no_messages: cli.no_messages,
+        recursive: cli.recursive,
+        walk: WalkOptions {
+            include: optional_glob_set(&cli.include)?,
+            exclude: optional_glob_set(&cli.exclude)?,
+            exclude_dir: optional_glob_set(&cli.exclude_dir)?,
+            include_dir: optional_glob_set(&cli.include_dir)?,
+            globs: path_globs(&cli.glob, &cli.iglob)?,
+            no_ignore: cli.no_ignore,
+            no_ignore_vcs: cli.no_ignore_vcs,
+            no_ignore_parent: cli.no_ignore_parent,
+            no_ignore_dot: cli.no_ignore_dot,
+            ignore_files: checked_ignore_files(cli.ignore_file)?,
+            hidden: cli.hidden,
+            types: optional_types(&cli.type_add, &cli.file_type, &cli.file_type_not)?,
+            max_filesize: cli.max_filesize,
+            max_depth: cli.max_depth,
+            follow: cli.follow,
+            sort: cli.sort.or(cli.sortr).unwrap_or_default(),
+            sort_reverse: cli.sortr.is_some(),
+        },
+        null: cli.null,
+        null_data: cli.null_data,
+        colors: (!cli.json && use_color(cli.color, cli.output_file.is_some())).then(|| {
+            std::env::var("GREP_COLORS").map_or_else(|_| Colors::default(), |spec| Colors::from_grep_colors(&spec))
+        }),
+        binary_files: cli.binary_files,
+        binary_offset: cli.binary_offset,
+        line_buffered: cli.line_buffered,
+        label: cli.label,
+        path_format: cli.path_format,
+        byte_offset: cli.byte_offset,
+        group_separator: cli.group_separator,
+        no_group_separator: cli.no_group_separator,
+        threads: cli.threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())),
+        json: cli.json,
+        replace: cli.replace,
+        multiline: cli.multiline,
+        search_zip: cli.search_zip,
+        pre: cli.pre,
+        encoding: cli.encoding,
+        mmap: cli.mmap,
+        mmap_threshold: cli.mmap_threshold,
+        vimgrep: cli.vimgrep,
+        column: cli.column,
+        passthru: cli.passthru,
+        // Quickfix lists need the name on every line.
+        heading: !cli.vimgrep && (cli.heading || (!cli.no_heading && cli.output_file.is_none() && io::stdout().is_terminal())),
+    };
+
+    if cli.benchmark {
+        benchmark(&cli.file_names, matcher.as_ref(), &options)?;
+        return Ok(ExitCode::SUCCESS);
     }
 
-    #[test]
-    fn test_should_write_line_match_and_no_invert_with_count() -> Result<()> {
-        let invert = false;
-        let is_match = true;
-        let count_matching_lines = true;
+    let mut out: Box<dyn Write> = match &cli.output_file {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(err) => {
+                eprintln!("rusty_grep: {}: {}", path, err);
+                return Ok(ExitCode::from(2));
+            }
+        },
+        // Like grep, block-buffer unless someone is watching or asked for each line.
+        None if io::stdout().is_terminal() => Box::new(io::stdout()),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let start = Instant::now();
+    let summary = if cli.files {
+        list_files(&cli.file_names, &options, &mut out)?
+    }
+    else {
+        search(&cli.file_names, matcher.as_ref(), &options, &mut out)?
+    };
 
-        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+    let mut finish = || {
+        if cli.stats && !cli.json {
+            write!(out, "{}", summary.to_stats(start.elapsed(), options.output_eol))?;
+        }
+        out.flush()
+    };
+    match finish() {
+        // `search` already stopped quietly when the reader went away (`| head`).
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        finished => finished?,
+    }
 
-        Ok(())
+    if cli.summary_json {
+        eprintln!("{}", summary.to_json(start.elapsed().as_millis()));
     }
 
-    #[test]
-    fn test_should_write_line_match_and_invert_with_count() -> Result<()> {
-        let invert = true;
-        let is_match = true;
-        let count_matching_lines = true;
+    Ok(exit_code(&summary, options.quiet))
+}
+
+/// grep's convention: 0 when some line was selected, 1 when none was, and 2
+/// when a file couldn't be searched. Under `-q` a match answers the question
+/// even if some other file couldn't be read.
+fn exit_code(summary: &SearchSummary, quiet: bool) -> ExitCode {
+    let matched = summary.files_matched > 0;
 
-        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+    if summary.had_errors && !(quiet && matched) {
+        ExitCode::from(2)
+    }
+    else if matched {
+        ExitCode::SUCCESS
+    }
+    else {
+        ExitCode::FAILURE
+    }
+}
 
-        Ok(())
+/// The dialect the patterns are in; the single-letter flags win over --pattern-type.
+fn pattern_type(cli: &Cli) -> PatternType {
+    #[cfg(feature = "pcre")]
+    if cli.perl_regexp {
+        return PatternType::Perl;
     }
 
-    #[test]
-    fn test_should_write_line_no_match_and_invert_with_count() -> Result<()> {
-        let invert = false;
-        let is_match = true;
-        let count_matching_lines = true;
+    if cli.fixed_strings {
+        PatternType::Fixed
+    }
+    else if cli.basic_regexp {
+        PatternType::Basic
+    }
+    else if cli.extended_regexp {
+        PatternType::Extended
+    }
+    else {
+        cli.pattern_type
+    }
+}
 
-        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+/// Resolves `--color=auto` by checking whether output goes to a terminal
+/// that understands escape sequences.
+fn use_color(choice: ColorChoice, to_file: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => !to_file && io::stdout().is_terminal() && std::env::var_os("TERM").is_some_and(|term| term != "dumb"),
+    }
+}
 
-        Ok(())
+/// Compiles repeatable glob options, with `None` meaning none were given.
+fn optional_glob_set(globs: &[String]) -> Result<Option<GlobSet>> {
+    if globs.is_empty() {
+        return Ok(None);
     }
 
-    #[test]
-    fn test_should_write_line_no_match_and_no_invert_with_count() -> Result<()> {
-        let invert = false;
-        let is_match = false;
-        let count_matching_lines = true;
+    Ok(Some(build_glob_set(globs)?))
+}
+
+/// Gathers the `-g` and `--iglob` filters, checking that each one compiles.
+fn path_globs(globs: &[String], iglobs: &[String]) -> Result<Vec<PathGlob>> {
+    let globs = globs.iter().map(|glob| (glob, false)).chain(iglobs.iter().map(|glob| (glob, true)));
+    let globs: Vec<PathGlob> = globs.map(|(glob, case_insensitive)| PathGlob { glob: glob.clone(), case_insensitive }).collect();
+    build_overrides(Path::new(""), &globs)?;
+
+    Ok(globs)
+}
 
-        assert_eq!(should_write_line(is_match, invert,  count_matching_lines), false);
+/// Makes sure every `--ignore-file` exists and holds valid rules.
+fn checked_ignore_files(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    for path in &paths {
+        // Unlike `Gitignore::new`, the builder reports a missing file.
+        if let Some(err) = GitignoreBuilder::new("").add(path) {
+            return Err(err.into());
+        }
+    }
+
+    Ok(paths)
+}
 
-        Ok(())
+fn optional_types(definitions: &[String], select: &[String], negate: &[String]) -> Result<Option<Types>> {
+    if definitions.is_empty() && select.is_empty() && negate.is_empty() {
+        return Ok(None);
     }
+
+    Ok(Some(build_types(definitions, select, negate)?))
+}
+
+/// Writes the completion script for `shell`. `--type` takes any name, since
+/// `--type-add` can define more, but the built-in ones are offered.
+fn write_completions<W: Write>(shell: Shell, out: &mut W) -> Result<()> {
+    let type_names = default_type_names();
+    let mut command = Cli::command()
+        .mut_arg("file_type", |arg| arg.value_parser(PossibleValuesParser::new(type_names.clone())))
+        .mut_arg("file_type_not", |arg| arg.value_parser(PossibleValuesParser::new(type_names)));
+
+    // `generate` panics if writing fails, so a closed pipe is left to `write_all`.
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, "rusty_grep", &mut script);
+    out.write_all(&script)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Runs the search with output discarded and reports throughput on stderr,
+/// counting the bytes the search read so directories walked with `-r` count too.
+fn benchmark(file_names: &[String], matcher: &dyn Matcher, options: &SearchOptions) -> Result<()> {
+    let start = Instant::now();
+    let summary = search(file_names, matcher, options, io::sink())?;
+    let seconds = start.elapsed().as_secs_f64().max(f64::EPSILON);
+
+    eprintln!(
+        "{} lines, {} bytes in {:.3}s: {:.0} lines/sec, {:.0} bytes/sec",
+        summary.total_lines,
+        summary.total_bytes,
+        seconds,
+        summary.total_lines as f64 / seconds,
+        summary.total_bytes as f64 / seconds,
+    );
+
+    Ok(())
 }
@@ -0,0 +1,192 @@
+use std::ops::Range;
+
+use memchr::memmem::Finder;
+use regex::Regex;
+
+/// Something that can find a pattern in a line of text. The search loop only
+/// talks to this trait, so the regex engine and the literal fast path are
+/// interchangeable. Matchers are shared between threads by `--threads`.
+pub trait Matcher: Sync {
+    /// Returns true if the pattern occurs anywhere in `line`.
+    fn is_match(&self, line: &str) -> bool;
+
+    /// Returns the byte ranges of successive non-overlapping matches in `line`.
+    fn find_iter<'a>(&'a self, line: &'a str) -> Box<dyn Iterator<Item = Range<usize>> + 'a>;
+
+    /// Returns each match in `line` with what `--replace` rewrites it to:
+    /// `template` with `$1`/`${name}` references expanded. Matchers without
+    /// capture groups only know `$0`, the whole match.
+    fn replacements<'a>(&'a self, line: &'a str, template: &'a str) -> Box<dyn Iterator<Item = (Range<usize>, String)> + 'a> {
+        Box::new(self.find_iter(line).map(move |span| {
+            let replacement = expand_whole_match(template, &line[span.clone()]);
+            (span, replacement)
+        }))
+    }
+}
+
+impl Matcher for Regex {
+    fn is_match(&self, line: &str) -> bool {
+        Regex::is_match(self, line)
+    }
+
+    fn find_iter<'a>(&'a self, line: &'a str) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        Box::new(Regex::find_iter(self, line).map(|m| m.range()))
+    }
+
+    fn replacements<'a>(&'a self, line: &'a str, template: &'a str) -> Box<dyn Iterator<Item = (Range<usize>, String)> + 'a> {
+        Box::new(self.captures_iter(line).map(move |captures| {
+            let mut replacement = String::new();
+            captures.expand(template, &mut replacement);
+            (captures.get_match().range(), replacement)
+        }))
+    }
+}
+
+/// The Perl-compatible engine can fail mid-search when a pattern backtracks
+/// too much; such a line is treated as not matching.
+#[cfg(feature = "pcre")]
+impl Matcher for fancy_regex::Regex {
+    fn is_match(&self, line: &str) -> bool {
+        fancy_regex::Regex::is_match(self, line).unwrap_or(false)
+    }
+
+    fn find_iter<'a>(&'a self, line: &'a str) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        Box::new(fancy_regex::Regex::find_iter(self, line).map_while(Result::ok).map(|m| m.range()))
+    }
+
+    fn replacements<'a>(&'a self, line: &'a str, template: &'a str) -> Box<dyn Iterator<Item = (Range<usize>, String)> + 'a> {
+        Box::new(self.captures_iter(line).map_while(Result::ok).filter_map(move |captures| {
+            let mut replacement = String::new();
+            captures.expand(template, &mut replacement);
+            Some((captures.get(0)?.range(), replacement))
+        }))
+    }
+}
+
+/// Expands a `--replace` template for a match with no capture groups, by the
+/// regex crate's rules: `$0` or `${0}` is the match, `$$` is a literal `$`,
+/// and any other group expands to nothing.
+fn expand_whole_match(template: &str, matched: &str) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') && let Some(end) = braced.find('}') {
+            (&braced[..end], &braced[end + 1..])
+        }
+        else {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+
+        if name.is_empty() {
+            // Not a reference, so the `$` stands for itself.
+            expanded.push('$');
+            continue;
+        }
+
+        if name == "0" {
+            expanded.push_str(matched);
+        }
+        rest = after;
+    }
+    expanded.push_str(rest);
+
+    expanded
+}
+
+/// Case-sensitive literal search using `memchr`'s substring finder, which
+/// avoids regex machinery entirely for plain strings.
+pub struct FixedStringMatcher {
+    finder: Finder<'static>,
+}
+
+impl FixedStringMatcher {
+    pub fn new(needle: &str) -> Self {
+        FixedStringMatcher { finder: Finder::new(needle).into_owned() }
+    }
+}
+
+impl Matcher for FixedStringMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        self.finder.find(line.as_bytes()).is_some()
+    }
+
+    fn find_iter<'a>(&'a self, line: &'a str) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        let len = self.finder.needle().len();
+        // An empty needle matches at every byte, including inside a character.
+        Box::new(
+            self.finder
+                .find_iter(line.as_bytes())
+                .filter(|&start| line.is_char_boundary(start))
+                .map(move |start| start..start + len),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_matcher_find_iter() {
+        let regex = Regex::new("a+").unwrap();
+        let matcher: &dyn Matcher = &regex;
+
+        assert!(matcher.is_match("baab"));
+        assert_eq!(matcher.find_iter("baab a").collect::<Vec<_>>(), vec![1..3, 5..6]);
+    }
+
+    #[test]
+    fn test_fixed_string_matcher_treats_metacharacters_literally() {
+        let matcher = FixedStringMatcher::new("a.b");
+
+        assert!(matcher.is_match("x a.b y"));
+        assert!(!matcher.is_match("x acb y"));
+    }
+
+    #[test]
+    fn test_fixed_string_matcher_find_iter() {
+        let matcher = FixedStringMatcher::new("aa");
+
+        assert_eq!(matcher.find_iter("aaaa b aa").collect::<Vec<_>>(), vec![0..2, 2..4, 7..9]);
+    }
+
+    #[test]
+    fn test_fixed_string_matcher_agrees_with_regex() {
+        let fixed = FixedStringMatcher::new("needle");
+        let regex = Regex::new("needle").unwrap();
+
+        for line in ["", "needle", "a needle in a needle stack", "needl", "NEEDLE"] {
+            assert_eq!(fixed.is_match(line), Matcher::is_match(&regex, line));
+            assert_eq!(fixed.find_iter(line).collect::<Vec<_>>(), Matcher::find_iter(&regex, line).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_regex_replacements_expand_captures() {
+        let regex = Regex::new(r"(?<key>\w+)=(\d+)").unwrap();
+        let matcher: &dyn Matcher = &regex;
+
+        assert_eq!(
+            matcher.replacements("a=1, bb=22", "${key}:$2").collect::<Vec<_>>(),
+            vec![(0..3, String::from("a:1")), (5..10, String::from("bb:22"))]
+        );
+    }
+
+    #[test]
+    fn test_fixed_string_replacements() {
+        let matcher = FixedStringMatcher::new("cat");
+
+        assert_eq!(matcher.replacements("a cat", "[$0] $$1 ${0}s $1 $").collect::<Vec<_>>(), vec![(2..5, String::from("[cat] $1 cats  $"))]);
+    }
+}
@@ -0,0 +1,116 @@
+//! `--multiline` support: the whole file is matched at once so a pattern can
+//! span lines, then each match is handed back to the line-by-line search
+//! loop as spans on the lines it touches.
+
+use std::ops::Range;
+
+use crate::Matcher;
+
+/// The parts of one line covered by multiline matches. It stands in for the
+/// real matcher while its line is processed, so the search loop selects,
+/// highlights and prints the line without knowing where the spans came from.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct LineMatches {
+    /// Whether any match touches the line, even if only its terminator.
+    touched: bool,
+    /// Each match's non-empty piece within the line's text.
+    spans: Vec<Range<usize>>,
+}
+
+impl Matcher for LineMatches {
+    fn is_match(&self, _line: &str) -> bool {
+        self.touched
+    }
+
+    fn find_iter<'a>(&'a self, _line: &'a str) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        Box::new(self.spans.iter().cloned())
+    }
+}
+
+/// Splits `text` into records ended by `terminator` (dropping a `\r` before a
+/// `\n`, as line-by-line reading does) and matches `matcher` against all of
+/// it. Returns each record with the bytes it took up, and what the matches
+/// cover on it.
+pub(crate) fn split_matches(text: &str, matcher: &dyn Matcher, terminator: u8) -> (Vec<(String, u64)>, Vec<LineMatches>) {
+    let mut records = Vec::new();
+    let mut bounds: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+
+    while start < text.len() {
+        let end = memchr::memchr(terminator, &text.as_bytes()[start..]).map_or(text.len(), |i| start + i + 1);
+        let mut content_end = end;
+        if text.as_bytes()[end - 1] == terminator {
+            content_end -= 1;
+            if terminator == b'\n' && content_end > start && text.as_bytes()[content_end - 1] == b'\r' {
+                content_end -= 1;
+            }
+        }
+
+        records.push((text[start..content_end].to_string(), (end - start) as u64));
+        bounds.push(start..end);
+        start = end;
+    }
+
+    let mut line_matches: Vec<LineMatches> = bounds.iter().map(|_| LineMatches::default()).collect();
+    for span in matcher.find_iter(text) {
+        // An empty match at the very end of the input isn't on any line.
+        let first = bounds.partition_point(|line| line.end <= span.start);
+        let last = bounds.partition_point(|line| line.end < span.end.max(span.start + 1));
+
+        for index in first..=last.min(bounds.len().saturating_sub(1)) {
+            let Some(line) = bounds.get(index) else {
+                break;
+            };
+            let line_matches = &mut line_matches[index];
+            line_matches.touched = true;
+
+            let content_end = line.start + records[index].0.len();
+            let (piece_start, piece_end) = (span.start.max(line.start), span.end.min(content_end));
+            if piece_start < piece_end {
+                line_matches.spans.push(piece_start - line.start..piece_end - line.start);
+            }
+        }
+    }
+
+    (records, line_matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_split_matches_spans_lines() {
+        let regex = Regex::new(r"foo\(\r?\n\s*bar").unwrap();
+        let (records, line_matches) = split_matches("x\nfoo(\r\n  bar)\nbaz", &regex, b'\n');
+
+        assert_eq!(records, vec![
+            (String::from("x"), 2),
+            (String::from("foo("), 6),
+            (String::from("  bar)"), 7),
+            (String::from("baz"), 3),
+        ]);
+        assert_eq!(line_matches.iter().map(|line| line.touched).collect::<Vec<_>>(), vec![false, true, true, false]);
+        assert_eq!(line_matches[1].spans, vec![0..4]);
+        assert_eq!(line_matches[2].spans, vec![0..5]);
+    }
+
+    #[test]
+    fn test_split_matches_terminator_only_match_touches_line() {
+        let regex = Regex::new(r"\n\n").unwrap();
+        let (_, line_matches) = split_matches("a\n\nb\n", &regex, b'\n');
+
+        assert!(line_matches[0].touched && line_matches[0].spans.is_empty());
+        assert!(line_matches[1].touched);
+        assert!(!line_matches[2].touched);
+    }
+
+    #[test]
+    fn test_split_matches_empty_match_at_end() {
+        let regex = Regex::new(r"(?m)$").unwrap();
+        let (_, line_matches) = split_matches("a\n", &regex, b'\n');
+
+        assert_eq!(line_matches, vec![LineMatches { touched: true, spans: vec![] }]);
+    }
+}
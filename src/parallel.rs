@@ -0,0 +1,153 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use crate::{for_each_target, record_file, report_error, search_file, uses_heading, write_line, FileSummary, Matcher, SearchOptions, SearchSummary, Target};
+
+/// What a worker made of one target, ready to be written out in turn.
+enum Outcome<'a> {
+    /// Everything the file produced, buffered so it can't interleave with
+    /// another file's lines.
+    Searched(Cow<'a, Path>, &'a Path, io::Result<FileSummary>, Vec<u8>),
+    Unusable(Cow<'a, Path>, &'a Path, String),
+    /// Left unread because the search had already been stopped.
+    Skipped,
+}
+
+/// Searches the targets under `roots` on `options.threads` workers. One
+/// thread lists the targets, the workers search them into buffers, and the
+/// calling thread writes the buffers to `out` in the order the targets were
+/// listed, so output looks the same as a one-by-one search.
+pub fn search<W: Write>(
+    roots: &[&Path],
+    matcher: &dyn Matcher,
+    options: &SearchOptions,
+    out: &mut W,
+    summary: &mut SearchSummary,
+) -> io::Result<()> {
+    let stop = AtomicBool::new(false);
+    let (job_sender, job_receiver) = mpsc::channel::<(usize, Target)>();
+    let job_receiver = Mutex::new(job_receiver);
+    let (outcome_sender, outcome_receiver) = mpsc::channel::<(usize, Outcome)>();
+
+    thread::scope(|scope| {
+        let stop = &stop;
+        let job_receiver = &job_receiver;
+
+        scope.spawn(move || {
+            let mut index = 0;
+            // Listing only fails if `visit` does, and this one never does.
+            let _ = for_each_target(roots, options, |target| {
+                if stop.load(Ordering::Relaxed) || job_sender.send((index, target)).is_err() {
+                    return Ok(ControlFlow::Break(()));
+                }
+                index += 1;
+                Ok(ControlFlow::Continue(()))
+            });
+        });
+
+        for _ in 0..options.threads {
+            let outcome_sender = outcome_sender.clone();
+            scope.spawn(move || {
+                loop {
+                    // The lock is only held while waiting for the next job, not while searching.
+                    let job = job_receiver.lock().unwrap().recv();
+                    let Ok((index, target)) = job else {
+                        break;
+                    };
+
+                    let outcome = match target {
+                        _ if stop.load(Ordering::Relaxed) => Outcome::Skipped,
+                        Target::File(path, root) => {
+                            let mut buf = Vec::new();
+                            let result = search_file(&path, root, matcher, options, &mut buf, false);
+                            Outcome::Searched(path, root, result, buf)
+                        }
+                        Target::Unusable(path, root, err) => Outcome::Unusable(path, root, err),
+                    };
+
+                    if outcome_sender.send((index, outcome)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(outcome_sender);
+
+        let mut waiting: BTreeMap<usize, Outcome> = BTreeMap::new();
+        let mut next_index = 0;
+        let mut wrote_lines = false;
+        let mut result = Ok(());
+
+        // Runs until every worker has finished and dropped its sender.
+        for (index, outcome) in outcome_receiver {
+            waiting.insert(index, outcome);
+
+            while let Some(outcome) = waiting.remove(&next_index) {
+                next_index += 1;
+
+                if stop.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                match write_outcome(outcome, options, out, summary, &mut wrote_lines) {
+                    Ok(ControlFlow::Continue(())) => {}
+                    Ok(ControlFlow::Break(())) => stop.store(true, Ordering::Relaxed),
+                    Err(err) => {
+                        stop.store(true, Ordering::Relaxed);
+                        result = Err(err);
+                    }
+                }
+            }
+        }
+
+        result
+    })
+}
+
+/// Writes one finished target. `wrote_lines` tracks whether any file's lines
+/// have been written yet, since workers can't know when their file needs the
+/// blank line that keeps `heading` groups apart.
+fn write_outcome<W: Write>(
+    outcome: Outcome,
+    options: &SearchOptions,
+    out: &mut W,
+    summary: &mut SearchSummary,
+    wrote_lines: &mut bool,
+) -> io::Result<ControlFlow<()>> {
+    match outcome {
+        Outcome::Searched(path, root, result, buf) => {
+            let written = write_buffer(&buf, options, out, wrote_lines);
+            // Counted even if the write failed, so a closed pipe still leaves
+            // this file's matches in the summary.
+            let flow = record_file(&path, root, result, options, summary);
+            written?;
+            flow
+        }
+        Outcome::Unusable(path, root, err) => {
+            report_error(options, summary, &path, root, err);
+            Ok(ControlFlow::Continue(()))
+        }
+        Outcome::Skipped => Ok(ControlFlow::Continue(())),
+    }
+}
+
+/// Writes the lines a worker buffered for one file.
+fn write_buffer<W: Write>(buf: &[u8], options: &SearchOptions, out: &mut W, wrote_lines: &mut bool) -> io::Result<()> {
+    if !buf.is_empty() {
+        if *wrote_lines && uses_heading(options) {
+            write_line(out, options.output_eol, format_args!(""))?;
+        }
+        *wrote_lines = true;
+    }
+    out.write_all(buf)?;
+    if options.line_buffered {
+        out.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::io::{self, BufRead};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before checking a followed file for appended data.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Yields raw records, terminator included, much like `BufRead::read_until`,
+/// but instead of ending at EOF it keeps polling for appended data the way
+/// `tail -f` does. A trailing record without its terminator is held back
+/// until the rest of it arrives.
+pub struct TailLines<R> {
+    reader: R,
+    poll_interval: Duration,
+    terminator: u8,
+    partial: Vec<u8>,
+}
+
+impl<R: BufRead> TailLines<R> {
+    pub fn new(reader: R, poll_interval: Duration, terminator: u8) -> Self {
+        TailLines { reader, poll_interval, terminator, partial: Vec::new() }
+    }
+}
+
+impl<R: BufRead> Iterator for TailLines<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.reader.read_until(self.terminator, &mut self.partial) {
+                Ok(_) if self.partial.last() == Some(&self.terminator) => return Some(Ok(std::mem::take(&mut self.partial))),
+                Ok(_) => thread::sleep(self.poll_interval),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::{BufReader, Write};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_tail_lines_yields_appended_lines() -> io::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        writeln!(tmp, "first")?;
+        let path = tmp.path().to_path_buf();
+
+        let reader = BufReader::new(std::fs::File::open(&path)?);
+        let mut lines = TailLines::new(reader, Duration::from_millis(10), b'\n');
+
+        assert_eq!(lines.next().unwrap()?, b"first\n");
+
+        let appender = thread::spawn(move || -> io::Result<()> {
+            thread::sleep(Duration::from_millis(50));
+            let mut file = OpenOptions::new().append(true).open(&path)?;
+            write!(file, "sec")?;
+            file.flush()?;
+            thread::sleep(Duration::from_millis(50));
+            writeln!(file, "ond")?;
+            Ok(())
+        });
+
+        assert_eq!(lines.next().unwrap()?, b"second\n");
+        appender.join().unwrap()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::matcher::Matcher;
+
+/// Returns true when some match of `matcher` in `line` starts and ends on a
+/// Unicode word boundary (UAX #29), rather than the `\b` assertion which
+/// treats a run of CJK characters as one long word.
+pub fn is_word_match(matcher: &dyn Matcher, line: &str) -> bool {
+    let boundaries = word_boundaries(line);
+
+    matcher
+        .find_iter(line)
+        .any(|m| !m.is_empty() && boundaries.contains(&m.start) && boundaries.contains(&m.end))
+}
+
+fn word_boundaries(line: &str) -> HashSet<usize> {
+    let mut boundaries: HashSet<usize> = line
+        .split_word_bound_indices()
+        .map(|(index, _)| index)
+        .collect();
+    boundaries.insert(line.len());
+
+    boundaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_is_word_match_cjk_word() {
+        let line = "東京都に住んでいます";
+        let regex = Regex::new("東京").unwrap();
+        let bounded = Regex::new(r"\b東京\b").unwrap();
+
+        // `\b` sees the whole line as a single word, so it never matches.
+        assert!(!bounded.is_match(line));
+        assert!(is_word_match(&regex, line));
+    }
+
+    #[test]
+    fn test_is_word_match_katakana_inside_word() {
+        let regex = Regex::new("コン").unwrap();
+
+        // Katakana runs segment as one word, so a partial match is rejected.
+        assert!(!is_word_match(&regex, "パソコンを買った"));
+    }
+
+    #[test]
+    fn test_is_word_match_ascii_whole_word() {
+        let regex = Regex::new("foo").unwrap();
+
+        assert!(is_word_match(&regex, "a foo b"));
+        assert!(!is_word_match(&regex, "a foobar b"));
+    }
+
+    #[test]
+    fn test_is_word_match_later_match_on_boundary() {
+        let regex = Regex::new("foo").unwrap();
+
+        assert!(is_word_match(&regex, "foobar foo"));
+    }
+}
@@ -0,0 +1,654 @@
+use std::cmp::Reverse;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use clap::ValueEnum;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::types::{Types, TypesBuilder};
+use ignore::WalkBuilder;
+use log::debug;
+
+/// Order in which a recursive search visits files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortBy {
+    /// By path, one directory at a time
+    #[default]
+    Path,
+    /// By last modification time, oldest first
+    Modified,
+    /// By size, smallest first
+    Size,
+}
+
+/// A `-g`/`--iglob` filter on paths relative to the search root, in
+/// gitignore syntax: a glob selects the files it matches, and one starting
+/// with `!` skips them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathGlob {
+    pub glob: String,
+    pub case_insensitive: bool,
+}
+
+/// Settings that decide which files a recursive search visits.
+#[derive(Default)]
+pub struct WalkOptions {
+    /// When set, only files whose name matches one of these globs are searched.
+    pub include: Option<GlobSet>,
+    /// Files whose name matches one of these globs are skipped, even if included.
+    pub exclude: Option<GlobSet>,
+    /// Directories whose name matches one of these globs aren't descended into.
+    pub exclude_dir: Option<GlobSet>,
+    /// When set, only directories whose name matches one of these globs, and
+    /// everything under them, are descended into. `exclude_dir` wins.
+    pub include_dir: Option<GlobSet>,
+    /// Filters on the path relative to the root, applied in order.
+    pub globs: Vec<PathGlob>,
+    /// Search files that any ignore rules would otherwise hide; the same as
+    /// setting all three of the switches below.
+    pub no_ignore: bool,
+    /// Disregard `.gitignore` and `.git/info/exclude`.
+    pub no_ignore_vcs: bool,
+    /// Disregard ignore files in the directories above the root.
+    pub no_ignore_parent: bool,
+    /// Disregard `.ignore` files.
+    pub no_ignore_dot: bool,
+    /// Extra files of gitignore rules that apply to the whole walk, with
+    /// lower precedence than the ignore files found along the way. These
+    /// still apply with `no_ignore`.
+    pub ignore_files: Vec<PathBuf>,
+    /// Search hidden files and descend into hidden directories.
+    pub hidden: bool,
+    /// Restricts the search to, or away from, named file types such as `rust`.
+    pub types: Option<Types>,
+    /// Skip files larger than this many bytes.
+    pub max_filesize: Option<u64>,
+    /// Descend at most this many levels below the root; 1 visits only the
+    /// files directly inside it.
+    pub max_depth: Option<usize>,
+    /// Follow symbolic links to files and directories instead of skipping
+    /// them. A link back to a directory being walked is reported as an error.
+    pub follow: bool,
+    pub sort: SortBy,
+    /// Visit files in the opposite of `sort` order.
+    pub sort_reverse: bool,
+}
+
+impl WalkOptions {
+    /// Whether a file found while recursing should be searched. Globs are
+    /// matched against the file name alone, as grep's `--include` does.
+    fn includes_file(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+
+        if self.include.as_ref().is_some_and(|include| !include.is_match(name)) {
+            debug!("skipping {}: not matched by --include", path.display());
+            return false;
+        }
+        if self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(name)) {
+            debug!("skipping {}: matched by --exclude", path.display());
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Compiles `globs` into a set that matches if any one of them does.
+pub fn build_glob_set<S: AsRef<str>>(globs: &[S]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob.as_ref())?);
+    }
+
+    builder.build()
+}
+
+/// Parses a file size such as `512`, `10K`, `10M` or `2G`. Suffixes are
+/// powers of 1024 and may be lower case.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match text.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&text[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&text[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&text[..i], 1 << 30),
+        _ => (text, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| String::from("expected a number with an optional K, M or G suffix"))
+}
+
+/// Builds a file type matcher from ripgrep's table of common types that
+/// only lets through files of a `select`ed type (or any type when none are
+/// selected) and never files of a `negate`d one. `definitions` such as
+/// `proto:*.proto` add types or extend existing ones, in ripgrep's
+/// `--type-add` syntax.
+pub fn build_types<S: AsRef<str>>(definitions: &[S], select: &[S], negate: &[S]) -> Result<Types, ignore::Error> {
+    let mut builder = TypesBuilder::new();
+    builder.add_defaults();
+    for definition in definitions {
+        builder.add_def(definition.as_ref())?;
+    }
+    for name in select {
+        builder.select(name.as_ref());
+    }
+    for name in negate {
+        builder.negate(name.as_ref());
+    }
+
+    builder.build()
+}
+
+/// Compiles `globs` into ripgrep-style overrides for the paths under `root`.
+/// Once any glob selects, only the files some glob selects are searched; a
+/// `!` glob skips files and whole directories. When several match, the last
+/// one wins, and overrides take precedence over `.gitignore` rules.
+pub fn build_overrides(root: &Path, globs: &[PathGlob]) -> Result<Override, ignore::Error> {
+    let mut builder = OverrideBuilder::new(root);
+    for glob in globs {
+        builder.case_insensitive(glob.case_insensitive)?;
+        builder.add(&glob.glob)?;
+    }
+
+    builder.build()
+}
+
+/// Names of the file types `build_types` knows without any `--type-add`.
+pub fn default_type_names() -> Vec<String> {
+    let mut builder = TypesBuilder::new();
+    builder.add_defaults();
+
+    builder.definitions().into_iter().map(|definition| definition.name().to_string()).collect()
+}
+
+/// Yields the regular files under `root` that `options` lets through, in
+/// `sort` order so that output is stable from run to run. Sorting by path
+/// happens as the walk goes; other orders need the whole list first. Inside a git
+/// repository, files ignored by `.gitignore` (at any level, including the
+/// repository's root above `root`) or `.git/info/exclude` are left out, as
+/// are files matched by an `.ignore` file anywhere; the `no_ignore` switches
+/// turn these off. Hidden files and directories (those whose name starts
+/// with a `.`, such as `.git`) are skipped unless `hidden` is set.
+pub fn walk<'a>(root: &Path, options: &'a WalkOptions) -> Box<dyn Iterator<Item = Result<PathBuf, ignore::Error>> + 'a> {
+    // The walker wants an owned filter, and globsets are cheap to clone.
+    let exclude_dir = options.exclude_dir.clone();
+    let include_dir = options.include_dir.clone();
+    let walk_root = root.to_path_buf();
+    let reverse_names = options.sort == SortBy::Path && options.sort_reverse;
+
+    let mut builder = WalkBuilder::new(root);
+    if let Some(types) = &options.types {
+        builder.types(types.clone());
+    }
+    // Files asked for by name apply even with `no_ignore`, as in ripgrep.
+    for ignore_file in &options.ignore_files {
+        // Rules that did parse still apply; checking the files is up to the caller.
+        let _ = builder.add_ignore(ignore_file);
+    }
+    if !options.globs.is_empty() {
+        match build_overrides(root, &options.globs) {
+            Ok(overrides) => {
+                builder.overrides(overrides);
+            }
+            Err(err) => return Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    let entries = builder
+        .standard_filters(false)
+        .hidden(!options.hidden)
+        .max_filesize(options.max_filesize)
+        .max_depth(options.max_depth)
+        .follow_links(options.follow)
+        .git_ignore(!options.no_ignore && !options.no_ignore_vcs)
+        .git_exclude(!options.no_ignore && !options.no_ignore_vcs)
+        .ignore(!options.no_ignore && !options.no_ignore_dot)
+        .parents(!options.no_ignore && !options.no_ignore_parent)
+        .sort_by_file_name(move |a, b| if reverse_names { b.cmp(a) } else { a.cmp(b) })
+        .filter_entry(move |entry| {
+            // The directory named on the command line is searched regardless.
+            if entry.depth() == 0 || !entry.file_type().is_some_and(|file_type| file_type.is_dir()) {
+                return true;
+            }
+
+            if exclude_dir.as_ref().is_some_and(|exclude_dir| exclude_dir.is_match(entry.file_name())) {
+                debug!("skipping {}: matched by --exclude-dir", entry.path().display());
+                return false;
+            }
+
+            // A directory inside an included one is included along with it.
+            let included = include_dir.as_ref().is_none_or(|include_dir| {
+                let relative = entry.path().strip_prefix(&walk_root).unwrap_or(entry.path());
+                relative.components().any(|component| include_dir.is_match(component))
+            });
+            if !included {
+                debug!("skipping {}: not matched by --include-dir", entry.path().display());
+            }
+            included
+        })
+        .build()
+        .filter_map(move |entry| match entry {
+            Ok(entry) => {
+                let is_file = entry.file_type().is_some_and(|file_type| file_type.is_file());
+                (is_file && options.includes_file(entry.path())).then(|| Ok(entry.into_path()))
+            }
+            Err(err) => Some(Err(err)),
+        });
+
+    let key: fn(&Path) -> Option<u128> = match options.sort {
+        SortBy::Path => return Box::new(entries),
+        SortBy::Modified => |path| {
+            let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok()?;
+            Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+        },
+        SortBy::Size => |path| fs::metadata(path).ok().map(|metadata| u128::from(metadata.len())),
+    };
+
+    // Errors and files that vanished have no key, so sort as the smallest. The
+    // sort is stable, so ties stay in path order.
+    let mut entries: Vec<(Option<u128>, Result<PathBuf, ignore::Error>)> = entries
+        .map(|entry| (entry.as_ref().ok().and_then(|path| key(path)), entry))
+        .collect();
+    if options.sort_reverse {
+        entries.sort_by_key(|(key, _)| Reverse(*key));
+    }
+    else {
+        entries.sort_by_key(|(key, _)| *key);
+    }
+
+    Box::new(entries.into_iter().map(|(_, entry)| entry))
+}
+
+/// The path an error from `walk` is about, such as a directory that couldn't
+/// be read, and what went wrong there. Errors that don't name a path, like a
+/// bad `--glob`, give `None`.
+pub fn error_path(err: &ignore::Error) -> Option<(&Path, String)> {
+    match err {
+        ignore::Error::WithPath { path, err } => Some((path, error_cause(err))),
+        ignore::Error::WithDepth { err, .. } => error_path(err),
+        _ => None,
+    }
+}
+
+/// An error's message without the path the walker puts in front of it, and
+/// for an OS error just its description, as grep prints it.
+fn error_cause(err: &ignore::Error) -> String {
+    match err {
+        ignore::Error::WithDepth { err, .. } => error_cause(err),
+        // An I/O error from the walker wraps the OS error in one that repeats the path.
+        ignore::Error::Io(err) => match err.source().and_then(|source| source.downcast_ref::<io::Error>()) {
+            Some(os_error) => os_error_message(os_error),
+            None => os_error_message(err),
+        },
+        err => err.to_string(),
+    }
+}
+
+/// `err`'s message without the ` (os error N)` that follows an OS error's description.
+fn os_error_message(err: &io::Error) -> String {
+    let message = err.to_string();
+    match err.raw_os_error() {
+        Some(code) => message.strip_suffix(&format!(" (os error {})", code)).unwrap_or(&message).to_string(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walked(root: &Path, options: &WalkOptions) -> Vec<PathBuf> {
+        walk(root, options)
+            .map(|path| path.unwrap().strip_prefix(root).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn test_walk_finds_nested_files_in_order() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("b.txt"), "")?;
+        fs::write(dir.path().join("a.rs"), "")?;
+        fs::write(dir.path().join("sub").join("c.rs"), "")?;
+
+        let paths = walked(dir.path(), &WalkOptions::default());
+
+        assert_eq!(paths, vec![PathBuf::from("a.rs"), PathBuf::from("b.txt"), Path::new("sub").join("c.rs")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_include_matches_file_names() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("src.rs"))?;
+        fs::write(dir.path().join("a.rs"), "")?;
+        fs::write(dir.path().join("b.txt"), "")?;
+        fs::write(dir.path().join("src.rs").join("c.md"), "")?;
+
+        let options = WalkOptions { include: Some(build_glob_set(&["*.rs", "*.toml"]).unwrap()), ..Default::default() };
+        let paths = walked(dir.path(), &options);
+
+        // Directories are always descended into; only files are filtered.
+        assert_eq!(paths, vec![PathBuf::from("a.rs")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_globs_match_relative_paths() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("src"))?;
+        fs::create_dir(dir.path().join("tests"))?;
+        fs::write(dir.path().join("src").join("lib.rs"), "")?;
+        fs::write(dir.path().join("src").join("README.md"), "")?;
+        fs::write(dir.path().join("tests").join("cli.rs"), "")?;
+        fs::write(dir.path().join("Cargo.TOML"), "")?;
+
+        let glob = |glob: &str, case_insensitive| PathGlob { glob: glob.to_string(), case_insensitive };
+        let options = WalkOptions { globs: vec![glob("*.rs", false), glob("!tests/*", false), glob("*.toml", true)], ..Default::default() };
+
+        assert_eq!(walked(dir.path(), &options), vec![PathBuf::from("Cargo.TOML"), Path::new("src").join("lib.rs")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_exclude_and_exclude_dir() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("target").join("debug"))?;
+        fs::create_dir(dir.path().join("src"))?;
+        fs::write(dir.path().join("target").join("debug").join("out.rs"), "")?;
+        fs::write(dir.path().join("src").join("lib.rs"), "")?;
+        fs::write(dir.path().join("src").join("lib.rs.orig"), "")?;
+        fs::write(dir.path().join("build.rs"), "")?;
+
+        let options = WalkOptions {
+            include: Some(build_glob_set(&["*.rs*"]).unwrap()),
+            exclude: Some(build_glob_set(&["*.orig", "build.*"]).unwrap()),
+            exclude_dir: Some(build_glob_set(&["target"]).unwrap()),
+            ..Default::default()
+        };
+        let paths = walked(dir.path(), &options);
+
+        assert_eq!(paths, vec![Path::new("src").join("lib.rs")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_include_dir() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for sub in [&["src", "net"][..], &["tests"], &["docs"], &["src", "gen"]] {
+            let path: PathBuf = sub.iter().collect();
+            fs::create_dir_all(dir.path().join(&path))?;
+            fs::write(dir.path().join(&path).join("a.txt"), "")?;
+        }
+        fs::write(dir.path().join("src").join("a.txt"), "")?;
+        fs::write(dir.path().join("top.txt"), "")?;
+
+        let options = WalkOptions {
+            include_dir: Some(build_glob_set(&["src", "tests"]).unwrap()),
+            exclude_dir: Some(build_glob_set(&["gen"]).unwrap()),
+            ..Default::default()
+        };
+        let expected: Vec<PathBuf> = ["src/a.txt", "src/net/a.txt", "tests/a.txt", "top.txt"].iter().map(|path| path.split('/').collect()).collect();
+        assert_eq!(walked(dir.path(), &options), expected);
+
+        // A root named on the command line is searched whatever its name.
+        assert_eq!(walked(&dir.path().join("docs"), &options), vec![PathBuf::from("a.txt")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_exclude_dir_spares_root() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("target"))?;
+        fs::write(dir.path().join("target").join("a.txt"), "")?;
+
+        let options = WalkOptions { exclude_dir: Some(build_glob_set(&["target"]).unwrap()), ..Default::default() };
+        let root = dir.path().join("target");
+
+        assert_eq!(walked(&root, &options), vec![PathBuf::from("a.txt")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_respects_gitignore() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join(".git"))?;
+        fs::create_dir_all(dir.path().join("src").join("gen"))?;
+        fs::write(dir.path().join(".gitignore"), "*.log\n/target/\n")?;
+        fs::write(dir.path().join("src").join(".gitignore"), "gen/\n!keep.log\n")?;
+        fs::create_dir(dir.path().join("target"))?;
+        fs::write(dir.path().join("target").join("out.rs"), "")?;
+        fs::write(dir.path().join("src").join("gen").join("parser.rs"), "")?;
+        fs::write(dir.path().join("src").join("lib.rs"), "")?;
+        fs::write(dir.path().join("src").join("debug.log"), "")?;
+        fs::write(dir.path().join("src").join("keep.log"), "")?;
+
+        let options = WalkOptions { include: Some(build_glob_set(&["*.rs", "*.log"]).unwrap()), ..Default::default() };
+        let src = dir.path().join("src");
+
+        // The root's .gitignore still applies when only a subdirectory is searched.
+        assert_eq!(walked(&src, &options), vec![PathBuf::from("keep.log"), PathBuf::from("lib.rs")]);
+
+        let options = WalkOptions { no_ignore: true, ..options };
+        assert_eq!(walked(&src, &options).len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_no_ignore_layers() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join(".git"))?;
+        fs::create_dir(dir.path().join("src"))?;
+        fs::write(dir.path().join(".gitignore"), "*.log\n")?;
+        fs::write(dir.path().join("src").join(".gitignore"), "*.tmp\n")?;
+        fs::write(dir.path().join("src").join(".ignore"), "*.bak\n")?;
+        for name in ["a.rs", "b.log", "c.tmp", "d.bak"] {
+            fs::write(dir.path().join("src").join(name), "")?;
+        }
+        let src = dir.path().join("src");
+        let walked_with = |options: WalkOptions| walked(&src, &options);
+
+        assert_eq!(walked_with(WalkOptions::default()), vec![PathBuf::from("a.rs")]);
+        assert_eq!(walked_with(WalkOptions { no_ignore_parent: true, ..Default::default() }), vec![PathBuf::from("a.rs"), PathBuf::from("b.log")]);
+        assert_eq!(walked_with(WalkOptions { no_ignore_dot: true, ..Default::default() }), vec![PathBuf::from("a.rs"), PathBuf::from("d.bak")]);
+        assert_eq!(
+            walked_with(WalkOptions { no_ignore_vcs: true, ..Default::default() }),
+            vec![PathBuf::from("a.rs"), PathBuf::from("b.log"), PathBuf::from("c.tmp")]
+        );
+        assert_eq!(walked_with(WalkOptions { no_ignore: true, ..Default::default() }).len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_ignore_files() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let rules = tempfile::NamedTempFile::new()?;
+        fs::write(rules.path(), "*.log\nfixtures/\n")?;
+        fs::create_dir(dir.path().join("fixtures"))?;
+        fs::write(dir.path().join("fixtures").join("a.rs"), "")?;
+        fs::write(dir.path().join("main.rs"), "")?;
+        fs::write(dir.path().join("run.log"), "")?;
+        fs::write(dir.path().join("other.rs"), "")?;
+        fs::write(dir.path().join(".ignore"), "other.rs\n")?;
+
+        let options = WalkOptions { ignore_files: vec![rules.path().to_path_buf()], ..Default::default() };
+        assert_eq!(walked(dir.path(), &options), vec![PathBuf::from("main.rs")]);
+
+        // `no_ignore` turns off the .ignore file but not the one given by name.
+        let options = WalkOptions { no_ignore: true, ..options };
+        assert_eq!(walked(dir.path(), &options), vec![PathBuf::from("main.rs"), PathBuf::from("other.rs")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_skips_hidden() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join(".git"))?;
+        fs::write(dir.path().join(".git").join("config"), "")?;
+        fs::write(dir.path().join(".env"), "")?;
+        fs::write(dir.path().join("main.rs"), "")?;
+
+        assert_eq!(walked(dir.path(), &WalkOptions::default()), vec![PathBuf::from("main.rs")]);
+
+        let options = WalkOptions { hidden: true, ..Default::default() };
+        assert_eq!(
+            walked(dir.path(), &options),
+            vec![PathBuf::from(".env"), Path::new(".git").join("config"), PathBuf::from("main.rs")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_hidden_root_is_searched() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join(".config");
+        fs::create_dir(&root)?;
+        fs::write(root.join("settings.toml"), "")?;
+
+        assert_eq!(walked(&root, &WalkOptions::default()), vec![PathBuf::from("settings.toml")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_types() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("main.rs"), "")?;
+        fs::write(dir.path().join("app.js"), "")?;
+        fs::write(dir.path().join("README.md"), "")?;
+
+        let options = WalkOptions { types: Some(build_types(&[], &["rust", "md"], &[]).unwrap()), ..Default::default() };
+        assert_eq!(walked(dir.path(), &options), vec![PathBuf::from("README.md"), PathBuf::from("main.rs")]);
+
+        let options = WalkOptions { types: Some(build_types(&[], &[], &["js"]).unwrap()), ..Default::default() };
+        assert_eq!(walked(dir.path(), &options), vec![PathBuf::from("README.md"), PathBuf::from("main.rs")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_max_filesize() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("small.txt"), "x".repeat(10))?;
+        fs::write(dir.path().join("big.txt"), "x".repeat(2000))?;
+
+        let options = WalkOptions { max_filesize: Some(parse_size("1K").unwrap()), ..Default::default() };
+
+        assert_eq!(walked(dir.path(), &options), vec![PathBuf::from("small.txt")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_max_depth() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("a").join("b"))?;
+        fs::write(dir.path().join("top.txt"), "")?;
+        fs::write(dir.path().join("a").join("mid.txt"), "")?;
+        fs::write(dir.path().join("a").join("b").join("deep.txt"), "")?;
+
+        let depth = |max_depth| walked(dir.path(), &WalkOptions { max_depth: Some(max_depth), ..Default::default() });
+
+        assert_eq!(depth(1), vec![PathBuf::from("top.txt")]);
+        assert_eq!(depth(2), vec![Path::new("a").join("mid.txt"), PathBuf::from("top.txt")]);
+        assert_eq!(depth(0), Vec::<PathBuf>::new());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_follow_symlinks() -> std::io::Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir()?;
+        let real = dir.path().join("real");
+        fs::create_dir(&real)?;
+        fs::write(real.join("a.txt"), "")?;
+        fs::create_dir(dir.path().join("root"))?;
+        symlink(&real, dir.path().join("root").join("linked"))?;
+        symlink(real.join("a.txt"), dir.path().join("root").join("b.txt"))?;
+        let root = dir.path().join("root");
+
+        assert_eq!(walked(&root, &WalkOptions::default()), Vec::<PathBuf>::new());
+
+        let options = WalkOptions { follow: true, ..Default::default() };
+        assert_eq!(walked(&root, &options), vec![PathBuf::from("b.txt"), Path::new("linked").join("a.txt")]);
+
+        // A link back up the tree is reported instead of walked forever.
+        symlink(&root, root.join("loop"))?;
+        let entries: Vec<_> = walk(&root, &options).collect();
+        assert_eq!(entries.iter().filter(|entry| entry.is_ok()).count(), 2);
+        assert!(entries.iter().any(|entry| entry.as_ref().is_err_and(|err| err.to_string().contains("loop"))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_sort() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("a.txt"), "xxx")?;
+        fs::write(dir.path().join("b.txt"), "x")?;
+        fs::write(dir.path().join("sub").join("c.txt"), "xx")?;
+
+        let sorted = |sort, sort_reverse| walked(dir.path(), &WalkOptions { sort, sort_reverse, ..Default::default() });
+        let (a, b, c) = (PathBuf::from("a.txt"), PathBuf::from("b.txt"), Path::new("sub").join("c.txt"));
+
+        assert_eq!(sorted(SortBy::Path, true), vec![c.clone(), b.clone(), a.clone()]);
+        assert_eq!(sorted(SortBy::Size, false), vec![b.clone(), c.clone(), a.clone()]);
+        assert_eq!(sorted(SortBy::Size, true), vec![a.clone(), c.clone(), b.clone()]);
+
+        let file = fs::File::options().write(true).open(dir.path().join("a.txt"))?;
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_000_000))?;
+        assert_eq!(sorted(SortBy::Modified, false)[0], a);
+        assert_eq!(sorted(SortBy::Modified, true)[2], a);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("10m"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10MB").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("99999999999999999G").is_err());
+    }
+
+    #[test]
+    fn test_build_types_rejects_unknown_type() {
+        assert!(build_types(&[], &["klingon"], &[]).is_err());
+    }
+
+    #[test]
+    fn test_default_type_names() {
+        let names = default_type_names();
+
+        assert!(names.iter().any(|name| name == "rust"));
+        assert!(!names.iter().any(|name| name == "klingon"));
+    }
+
+    #[test]
+    fn test_build_types_definitions() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("api.proto"), "")?;
+        fs::write(dir.path().join("main.rs"), "")?;
+        fs::write(dir.path().join("build.bzl"), "")?;
+
+        let types = build_types(&["proto:*.proto", "rust:*.bzl"], &["proto", "rust"], &[]).unwrap();
+        let options = WalkOptions { types: Some(types), ..Default::default() };
+
+        assert_eq!(
+            walked(dir.path(), &options),
+            vec![PathBuf::from("api.proto"), PathBuf::from("build.bzl"), PathBuf::from("main.rs")]
+        );
+        assert!(build_types(&["no-globs"], &[], &[]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1003 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use assert_cmd::Command;
+
+/// The binary, with any config file of the user's turned off so it can't
+/// change what the tests see.
+fn rusty_grep() -> Command {
+    let mut cmd = cargo_bin_cmd!("rusty_grep");
+    cmd.env("RUSTY_GREP_CONFIG", "");
+    cmd
+}
+
+#[test]
+fn test_directory_argument_is_reported_and_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    std::fs::write(&file, "hello\n").unwrap();
+
+    let assert = rusty_grep()
+        .arg("-h")
+        .arg("hello")
+        .arg(dir.path())
+        .arg(&file)
+        .assert()
+        .code(2)
+        .stdout("hello\n");
+
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert_eq!(stderr, format!("rusty_grep: {}: Is a directory\n", dir.path().display()));
+}
+
+#[test]
+fn test_output_file_receives_results() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    let output = dir.path().join("output.txt");
+    std::fs::write(&input, "hello\nworld\n").unwrap();
+    std::fs::write(&output, "stale contents that should be truncated\n").unwrap();
+
+    rusty_grep()
+        .arg("-O")
+        .arg(&output)
+        .arg("hello")
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout("");
+
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "hello\n");
+}
+
+#[test]
+fn test_output_file_that_cannot_be_created_exits_2() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    std::fs::write(&input, "hello\n").unwrap();
+
+    rusty_grep()
+        .arg("--output-file")
+        .arg(dir.path().join("missing").join("output.txt"))
+        .arg("hello")
+        .arg(&input)
+        .assert()
+        .code(2)
+        .stdout("");
+}
+
+#[test]
+fn test_benchmark_reports_throughput() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    std::fs::write(&input, "hello\nworld\n".repeat(1000)).unwrap();
+
+    let assert = rusty_grep()
+        .arg("--benchmark")
+        .arg("nothing-matches-this")
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout("");
+
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    let lines_per_sec: f64 = stderr
+        .split(": ")
+        .nth(1)
+        .and_then(|rates| rates.split(' ').next())
+        .and_then(|rate| rate.parse().ok())
+        .expect("throughput missing from stderr");
+    assert!(stderr.starts_with("2000 lines, 12000 bytes in "));
+    assert!(lines_per_sec > 0.0);
+}
+
+#[test]
+fn test_benchmark_counts_bytes_under_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("a.txt"), "hello\n".repeat(10)).unwrap();
+    std::fs::write(dir.path().join("sub").join("b.txt"), "world\n".repeat(5)).unwrap();
+
+    let assert = rusty_grep().current_dir(dir.path()).args(["--benchmark", "-r", "nothing"]).assert().success().stdout("");
+
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.starts_with("15 lines, 90 bytes in "), "{}", stderr);
+}
+
+#[test]
+fn test_summary_json_on_stderr() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.txt");
+    let second = dir.path().join("second.txt");
+    std::fs::write(&first, "hello hello\nworld\n").unwrap();
+    std::fs::write(&second, "world\n").unwrap();
+
+    let assert = rusty_grep()
+        .arg("--summary-json")
+        .arg("hello")
+        .arg(&first)
+        .arg(&second)
+        .assert()
+        .success()
+        .stdout(format!("{}:hello hello\n", first.display()));
+
+    let summary: serde_json::Value = serde_json::from_slice(&assert.get_output().stderr).unwrap();
+    assert_eq!(summary["files_searched"], 2);
+    assert_eq!(summary["files_matched"], 1);
+    assert_eq!(summary["total_matches"], 2);
+    assert_eq!(summary["total_lines"], 3);
+    assert_eq!(summary["had_errors"], false);
+    assert!(summary["elapsed_ms"].is_u64());
+}
+
+#[test]
+fn test_reads_stdin_without_file_arguments() {
+    rusty_grep()
+        .arg("hello")
+        .write_stdin("hello\nworld\nhello again\n")
+        .assert()
+        .success()
+        .stdout("hello\nhello again\n");
+}
+
+#[test]
+fn test_reads_stdin_for_dash_alongside_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    std::fs::write(&file, "hello from file\n").unwrap();
+
+    rusty_grep()
+        .arg("-n")
+        .arg("hello")
+        .arg("-")
+        .arg(&file)
+        .write_stdin("hello from stdin\n")
+        .assert()
+        .success()
+        .stdout(format!("(standard input):1:hello from stdin\n{}:1:hello from file\n", file.display()));
+}
+
+#[test]
+fn test_quiet_exit_status() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    std::fs::write(&file, "hello\n").unwrap();
+
+    rusty_grep()
+        .arg("-q")
+        .arg("hello")
+        .arg(&file)
+        .assert()
+        .code(0)
+        .stdout("");
+
+    rusty_grep()
+        .arg("-q")
+        .arg("goodbye")
+        .arg(&file)
+        .assert()
+        .code(1)
+        .stdout("");
+}
+
+#[test]
+fn test_repeated_regexp() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    std::fs::write(&file, "apple\nbanana\ncherry\n").unwrap();
+
+    rusty_grep()
+        .args(["-e", "apple", "-e", "cherry"])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout("apple\ncherry\n");
+}
+
+#[test]
+fn test_pattern_file_with_regexp() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    std::fs::write(&file, "apple\nbanana\ncherry\n").unwrap();
+    let pattern_file = dir.path().join("patterns.txt");
+    std::fs::write(&pattern_file, "APPLE\n").unwrap();
+
+    rusty_grep()
+        .arg("-i")
+        .arg("-f")
+        .arg(&pattern_file)
+        .args(["-e", "cherry"])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout("apple\ncherry\n");
+}
+
+#[test]
+fn test_missing_pattern_file() {
+    let assert = rusty_grep()
+        .args(["-f", "no/such/patterns.txt"])
+        .assert()
+        .code(2);
+
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.starts_with("rusty_grep: no/such/patterns.txt: "));
+}
+
+#[test]
+fn test_no_messages_skips_unreadable_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    std::fs::write(&file, "hello\n").unwrap();
+
+    rusty_grep()
+        .arg("-s")
+        .arg("-H")
+        .arg("hello")
+        .arg(dir.path().join("missing.txt"))
+        .arg(dir.path())
+        .arg(&file)
+        .assert()
+        .code(2)
+        .stdout(format!("{}:hello\n", file.display()))
+        .stderr("");
+}
+
+#[test]
+fn test_recursive_include() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join("src").join("main.rs"), "fn hello() {}\n").unwrap();
+    std::fs::write(dir.path().join("README.md"), "hello\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["-r", "--include=*.rs", "hello"])
+        .assert()
+        .success()
+        .stdout("./src/main.rs:fn hello() {}\n");
+}
+
+#[test]
+fn test_list_files_only_with_include_and_exclude() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join("src").join("main.rs"), "").unwrap();
+    std::fs::write(dir.path().join("src").join("main_test.rs"), "").unwrap();
+    std::fs::write(dir.path().join("build.rs"), "").unwrap();
+    std::fs::write(dir.path().join("README.md"), "").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--list-files-only", "--include=*.rs", "--exclude=*_test.rs"])
+        .assert()
+        .success()
+        .stdout("./build.rs\n./src/main.rs\n");
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    std::fs::write(&file, "hello\n").unwrap();
+
+    rusty_grep().arg("hello").arg(&file).assert().code(0);
+    rusty_grep().arg("goodbye").arg(&file).assert().code(1).stdout("");
+    rusty_grep().arg("-c").arg("--include-zero").arg("goodbye").arg(&file).assert().code(1).stdout("0\n");
+    rusty_grep().arg("hello").arg(&file).arg(dir.path().join("missing")).assert().code(2);
+    rusty_grep().arg("-q").arg("hello").arg(dir.path().join("missing")).arg(&file).assert().code(0);
+}
+
+/// Each of the count flags, with what it gives for `a.txt`.
+const COUNT_FLAGS: [(&str, &str); 3] = [("-c", "2"), ("--count-matches", "2"), ("--count-bytes", "10")];
+
+fn count_dir() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), "hello\nhello world\n").unwrap();
+    std::fs::write(dir.path().join("b.txt"), "nothing\n").unwrap();
+    dir
+}
+
+#[test]
+fn test_counts_omit_zero() {
+    let dir = count_dir();
+
+    for (flag, count) in COUNT_FLAGS {
+        rusty_grep().current_dir(dir.path()).args([flag, "hello", "b.txt"]).assert().code(1).stdout("");
+        rusty_grep()
+            .current_dir(dir.path())
+            .args([flag, "hello", "a.txt", "b.txt"])
+            .assert()
+            .success()
+            .stdout(format!("a.txt:{}\n", count));
+    }
+}
+
+#[test]
+fn test_counts_include_zero() {
+    let dir = count_dir();
+
+    for (flag, count) in COUNT_FLAGS {
+        rusty_grep().current_dir(dir.path()).args([flag, "--include-zero", "hello", "b.txt"]).assert().code(1).stdout("0\n");
+        rusty_grep()
+            .current_dir(dir.path())
+            .args([flag, "--include-zero", "hello", "a.txt", "b.txt"])
+            .assert()
+            .success()
+            .stdout(format!("a.txt:{}\nb.txt:0\n", count));
+    }
+}
+
+#[test]
+fn test_invalid_regex_exits_2() {
+    let assert = rusty_grep().arg("a(").arg("-").write_stdin("a(\n").assert().code(2);
+
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.starts_with("rusty_grep: regex parse error"));
+}
+
+#[test]
+fn test_label_names_stdin() {
+    rusty_grep()
+        .args(["-H", "--label=build.log", "error", "-"])
+        .write_stdin("ok\nerror: failed\n")
+        .assert()
+        .success()
+        .stdout("build.log:error: failed\n");
+
+    rusty_grep()
+        .args(["-l", "--label=build.log", "error"])
+        .write_stdin("error: failed\n")
+        .assert()
+        .success()
+        .stdout("build.log\n");
+}
+
+#[test]
+fn test_bare_color_is_auto() {
+    // Output is captured through a pipe, so `auto` leaves it plain.
+    rusty_grep().args(["--color", "b"]).write_stdin("abc\n").assert().success().stdout("abc\n");
+    rusty_grep()
+        .args(["--color=always", "b"])
+        .write_stdin("abc\n")
+        .assert()
+        .success()
+        .stdout("a\x1b[01;31m\x1b[Kb\x1b[m\x1b[Kc\n");
+}
+
+#[test]
+fn test_grep_colors_env() {
+    rusty_grep()
+        .env("GREP_COLORS", "ms=04:se=")
+        .args(["--color=always", "-n", "b"])
+        .write_stdin("abc\n")
+        .assert()
+        .success()
+        .stdout("\x1b[32m\x1b[K1\x1b[m\x1b[K:a\x1b[04m\x1b[Kb\x1b[m\x1b[Kc\n");
+}
+
+#[test]
+fn test_replace_preview() {
+    rusty_grep()
+        .args(["--replace=<$1>", "id=([0-9]+)"])
+        .write_stdin("id=7 ok\n")
+        .assert()
+        .success()
+        .stdout("<7> ok\n");
+
+    rusty_grep()
+        .args(["--replace=<$1>", "--color=always", "id=([0-9]+)"])
+        .write_stdin("id=7 ok\n")
+        .assert()
+        .success()
+        .stdout("\x1b[09;31m\x1b[Kid=7\x1b[m\x1b[K\x1b[32m\x1b[K<7>\x1b[m\x1b[K ok\n");
+}
+
+#[test]
+fn test_basic_and_extended_regexp_flags() {
+    rusty_grep()
+        .args(["-G", r"\(cat\|dog\)s\?$"])
+        .write_stdin("cats\ndog\n(cat|dog)\nbird\n")
+        .assert()
+        .success()
+        .stdout("cats\ndog\n");
+
+    rusty_grep()
+        .args(["--pattern-type=basic", "-E", "(cat|dog)s?$"])
+        .write_stdin("cats\ndog\nbird\n")
+        .assert()
+        .success()
+        .stdout("cats\ndog\n");
+}
+
+#[test]
+fn test_json_output() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), "one\nfoo foo\n").unwrap();
+    std::fs::write(dir.path().join("b.txt"), "nothing\n").unwrap();
+
+    let assert = rusty_grep()
+        .current_dir(dir.path())
+        .args(["--json", "-B1", "foo", "a.txt", "b.txt"])
+        .assert()
+        .success();
+
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let messages: Vec<serde_json::Value> = stdout.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let types: Vec<&str> = messages.iter().map(|message| message["type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["begin", "context", "match", "end", "summary"]);
+
+    let matched = &messages[2]["data"];
+    assert_eq!(matched["path"]["text"], "a.txt");
+    assert_eq!(matched["lines"]["text"], "foo foo\n");
+    assert_eq!(matched["line_number"], 2);
+    assert_eq!(matched["absolute_offset"], 4);
+    assert_eq!(matched["submatches"][1]["start"], 4);
+
+    let stats = &messages[4]["data"]["stats"];
+    assert_eq!(stats["searches"], 2);
+    assert_eq!(stats["searches_with_match"], 1);
+    assert_eq!(stats["matches"], 2);
+}
+
+#[test]
+fn test_stats() {
+    let assert = rusty_grep().args(["--stats", "foo"]).write_stdin("foo foo\nbar\n").assert().success();
+
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert!(stdout.starts_with("foo foo\n\n2 matches\n1 matched lines\n1 files contained matches\n1 files searched\n12 bytes searched\n"));
+    assert!(stdout.ends_with(" seconds\n"));
+
+    let assert = rusty_grep().args(["--stats", "--output-eol=crlf", "foo"]).write_stdin("foo foo\nbar\n").assert().success();
+
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert!(stdout.starts_with("foo foo\r\n\r\n2 matches\r\n1 matched lines\r\n"));
+    assert!(stdout.ends_with(" seconds\r\n"));
+}
+
+#[test]
+fn test_tail_rejects_several_files() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.log"), "error\n").unwrap();
+    std::fs::write(dir.path().join("b.log"), "error\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--tail", "error", "a.log", "b.log"])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .code(2)
+        .stdout("")
+        .stderr("rusty_grep: --tail follows a single file, but 2 were given\n");
+}
+
+#[test]
+fn test_smart_case() {
+    rusty_grep().args(["-S", "hello"]).write_stdin("Hello\nhello\n").assert().success().stdout("Hello\nhello\n");
+    rusty_grep().args(["-S", "Hello"]).write_stdin("Hello\nhello\n").assert().success().stdout("Hello\n");
+    rusty_grep().args(["-S", r"\Sello"]).write_stdin("HELLO\n").assert().success().stdout("HELLO\n");
+}
+
+#[test]
+fn test_search_zip() {
+    let dir = tempfile::tempdir().unwrap();
+    let plain = dir.path().join("app.log");
+    std::fs::write(&plain, "started\nerror: disk full\n").unwrap();
+    assert!(std::process::Command::new("gzip").arg(&plain).status().unwrap().success());
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--search-zip", "-n", "error", "app.log.gz"])
+        .assert()
+        .success()
+        .stdout("2:error: disk full\n");
+
+    rusty_grep().current_dir(dir.path()).args(["error", "app.log.gz"]).assert().code(1);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_pre_command() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("doc.txt"), "hello\nworld\n").unwrap();
+    let pre = dir.path().join("upper.sh");
+    std::fs::write(&pre, "#!/bin/sh\ntr a-z A-Z\n").unwrap();
+    std::fs::set_permissions(&pre, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .arg("--pre")
+        .arg(&pre)
+        .args(["WORLD", "doc.txt"])
+        .assert()
+        .success()
+        .stdout("WORLD\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recursive_unreadable_directory() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let locked = dir.path().join("locked");
+    std::fs::create_dir(&locked).unwrap();
+    std::fs::write(locked.join("b.txt"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("a.txt"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("z.txt"), "hello\n").unwrap();
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+    // Permissions don't stop root, so there's nothing to test there.
+    if std::fs::read_dir(&locked).is_err() {
+        let assert = rusty_grep().arg("-r").arg("hello").arg(dir.path()).assert().code(2).stdout(format!(
+            "{}:hello\n{}:hello\n",
+            dir.path().join("a.txt").display(),
+            dir.path().join("z.txt").display()
+        ));
+
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert_eq!(stderr, format!("rusty_grep: {}: Permission denied\n", locked.display()));
+
+        rusty_grep().arg("-rs").arg("hello").arg(dir.path()).assert().code(2).stderr("");
+    }
+
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_pre_command_with_large_stderr() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("doc.txt"), "hello\n").unwrap();
+    let pre = dir.path().join("noisy.sh");
+    // Far more than a pipe buffer holds, written before any of stdout.
+    std::fs::write(&pre, "#!/bin/sh\nhead -c 200000 /dev/zero | tr '\\0' x >&2\ncat\n").unwrap();
+    std::fs::set_permissions(&pre, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .arg("--pre")
+        .arg(&pre)
+        .args(["hello", "doc.txt"])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout("hello\n");
+}
+
+#[test]
+fn test_closed_output_pipe_exits_quietly() {
+    use std::io::{BufRead, BufReader, Read};
+    use std::process::Stdio;
+
+    let dir = tempfile::tempdir().unwrap();
+    // Far more output than a pipe buffer holds, so the search is still writing when the reader goes.
+    let lines: String = (0..200_000).map(|i| format!("match {i}\n")).collect();
+    std::fs::write(dir.path().join("big.txt"), lines).unwrap();
+
+    for args in [&["match", "big.txt"][..], &["-r", "-j", "2", "match", "."][..]] {
+        let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_rusty_grep"))
+            .env("RUSTY_GREP_CONFIG", "")
+            .current_dir(dir.path())
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // Like `| head -1`: take one line and close the pipe.
+        let mut first = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut first).unwrap();
+        assert!(first.contains("match 0"), "{args:?}: {first:?}");
+
+        let mut stderr = String::new();
+        child.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+        let status = child.wait().unwrap();
+        assert_eq!(stderr, "", "{args:?}");
+        assert_eq!(status.code(), Some(0), "{args:?}");
+    }
+}
+
+#[test]
+fn test_encoding_utf16le() {
+    let dir = tempfile::tempdir().unwrap();
+    let utf16: Vec<u8> = "boot ok\nboot failed\n".encode_utf16().flat_map(u16::to_le_bytes).collect();
+    std::fs::write(dir.path().join("event.log"), utf16).unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--encoding", "utf-16le", "-n", "failed", "event.log"])
+        .assert()
+        .success()
+        .stdout("2:boot failed\n");
+}
+
+#[test]
+fn test_encoding_whatwg_labels() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("menu.txt"), b"th\xe9\ncaf\xe9 \x80 2\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--encoding", "latin1", "café", "menu.txt"])
+        .assert()
+        .success()
+        .stdout("café € 2\n");
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--encoding", "klingon", "café", "menu.txt"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_vimgrep() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "todo: one\nfine\nx todo todo\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--vimgrep", "todo", "notes.txt"])
+        .assert()
+        .success()
+        .stdout("notes.txt:1:1:todo: one\nnotes.txt:3:3:x todo todo\nnotes.txt:3:8:x todo todo\n");
+}
+
+#[test]
+fn test_context_clamps_at_file_boundaries() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("first.txt"), "match\na\nb\n").unwrap();
+    std::fs::write(dir.path().join("second.txt"), "a\nmatch\nb\n").unwrap();
+    std::fs::write(dir.path().join("last.txt"), "a\nb\nmatch\n").unwrap();
+
+    let context = |flag: &str, file: &str| rusty_grep().current_dir(dir.path()).args(["-n", flag, "5", "match", file]).assert().success();
+
+    context("-B", "first.txt").stdout("1:match\n");
+    context("-B", "second.txt").stdout("1-a\n2:match\n");
+    context("-A", "last.txt").stdout("3:match\n");
+    context("-A", "second.txt").stdout("2:match\n3-b\n");
+}
+
+#[test]
+fn test_heading() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("b.txt"), "hello\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--heading", "hello", "a.txt", "b.txt"])
+        .assert()
+        .success()
+        .stdout("a.txt\nhello\n\nb.txt\nhello\n");
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--heading", "--no-heading", "hello", "a.txt", "b.txt"])
+        .assert()
+        .success()
+        .stdout("a.txt:hello\nb.txt:hello\n");
+}
+
+#[test]
+fn test_heading_skips_files_without_output() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("b.txt"), "nothing\n").unwrap();
+    std::fs::write(dir.path().join("c.txt"), "hello\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--heading", "hello", "a.txt", "b.txt", "c.txt"])
+        .assert()
+        .success()
+        .stdout("a.txt\nhello\n\nc.txt\nhello\n");
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["--heading", "-m", "0", "-A", "1", "hello", "a.txt", "c.txt"])
+        .assert()
+        .code(1)
+        .stdout("");
+}
+
+#[test]
+fn test_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config");
+    std::fs::write(&config, "# line numbers everywhere\n-n\n--insensitive\n").unwrap();
+
+    rusty_grep().env("RUSTY_GREP_CONFIG", &config).arg("hello").write_stdin("x\nHELLO\n").assert().success().stdout("2:HELLO\n");
+    rusty_grep().env("RUSTY_GREP_CONFIG", &config).args(["--no-config", "hello"]).write_stdin("x\nHELLO\n").assert().code(1);
+
+    let assert = rusty_grep().env("RUSTY_GREP_CONFIG", dir.path().join("missing")).arg("hello").write_stdin("hello\n").assert();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert.success().stdout("hello\n");
+    assert!(stderr.contains("missing"));
+}
+
+#[test]
+fn test_config_file_under_xdg_config_home() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("rusty_grep")).unwrap();
+    std::fs::write(dir.path().join("rusty_grep").join("config"), "-n\n").unwrap();
+
+    cargo_bin_cmd!("rusty_grep")
+        .env_remove("RUSTY_GREP_CONFIG")
+        .env("XDG_CONFIG_HOME", dir.path())
+        .arg("hello")
+        .write_stdin("x\nhello\n")
+        .assert()
+        .success()
+        .stdout("2:hello\n");
+    rusty_grep().env("XDG_CONFIG_HOME", dir.path()).arg("hello").write_stdin("x\nhello\n").assert().success().stdout("hello\n");
+}
+
+#[test]
+fn test_completions() {
+    let assert = rusty_grep().args(["completions", "bash"]).assert().success();
+    let script = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+
+    assert!(script.contains("--smart-case"));
+    assert!(script.contains("rust"));
+
+    rusty_grep().args(["completions", "tcsh"]).assert().code(2);
+}
+
+#[test]
+fn test_man_page() {
+    let assert = rusty_grep().arg("man").assert().success();
+    let page = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+
+    assert!(page.contains(".TH rusty_grep 1"));
+    assert!(page.contains(r"\-\-smart\-case"));
+}
+
+#[test]
+fn test_debug() {
+    let assert = rusty_grep().args(["--debug", "hello"]).write_stdin("hello\n").assert().success().stdout("hello\n");
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+
+    assert!(stderr.contains("compiled pattern: hello"), "{}", stderr);
+    assert!(stderr.contains("reading standard input"));
+}
+
+#[test]
+fn test_glob() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("tests")).unwrap();
+    std::fs::write(dir.path().join("main.rs"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("tests").join("cli.rs"), "hello\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["-r", "-g", "*.rs", "-g", "!tests/*", "hello"])
+        .assert()
+        .success()
+        .stdout("./main.rs:hello\n");
+    rusty_grep().current_dir(dir.path()).args(["-r", "-g", "[", "hello"]).assert().code(2);
+}
+
+#[test]
+fn test_no_ignore_parent() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join(".git")).unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+    std::fs::write(dir.path().join("src").join("main.rs"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("src").join("build.log"), "hello\n").unwrap();
+    let src = dir.path().join("src");
+
+    rusty_grep().current_dir(&src).args(["-r", "-l", "hello"]).assert().success().stdout("./main.rs\n");
+    rusty_grep()
+        .current_dir(&src)
+        .args(["-r", "-l", "--no-ignore-parent", "hello"])
+        .assert()
+        .success()
+        .stdout("./build.log\n./main.rs\n");
+}
+
+#[test]
+fn test_ignore_file() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join("src").join("main.rs"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("src").join("big.dump"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("search.ignore"), "*.dump\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["-r", "--ignore-file", "search.ignore", "hello", "src"])
+        .assert()
+        .success()
+        .stdout("src/main.rs:hello\n");
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["-r", "--no-ignore", "--ignore-file", "search.ignore", "hello", "src"])
+        .assert()
+        .success()
+        .stdout("src/main.rs:hello\n");
+    rusty_grep().current_dir(dir.path()).args(["-r", "--ignore-file", "missing.ignore", "hello", "src"]).assert().code(2);
+}
+
+#[test]
+fn test_mmap_threshold() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("log.txt"), "ok\nfailed\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let assert = rusty_grep()
+            .current_dir(dir.path())
+            .args(["--debug", "-n", "failed", "log.txt"])
+            .args(args)
+            .assert()
+            .success()
+            .stdout("2:failed\n");
+        String::from_utf8(assert.get_output().stderr.clone()).unwrap()
+    };
+
+    // log.txt is 10 bytes: files at least as big as the threshold are mapped.
+    assert!(run(&["--mmap-threshold=10"]).contains("log.txt: memory-mapped"));
+    assert!(run(&["--mmap-threshold=11"]).contains("log.txt: buffered reads"));
+    assert!(run(&["--mmap-threshold=1K"]).contains("log.txt: buffered reads"));
+    rusty_grep().args(["--mmap-threshold=lots", "x"]).assert().code(2);
+}
+
+#[test]
+fn test_no_mmap() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("log.txt"), "ok\nfailed\n").unwrap();
+
+    let assert = rusty_grep()
+        .current_dir(dir.path())
+        .args(["--debug", "--mmap", "--mmap-threshold=0", "--no-mmap", "failed", "log.txt"])
+        .assert()
+        .success()
+        .stdout("failed\n");
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+
+    assert!(stderr.contains("log.txt: buffered reads"), "{}", stderr);
+    assert!(!stderr.contains("memory-mapped"));
+}
+
+#[test]
+fn test_path_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    std::fs::create_dir_all(root.join("src").join("net")).unwrap();
+    std::fs::write(root.join("src").join("net").join("http.rs"), "hello\n").unwrap();
+
+    let run = |format: &str| {
+        rusty_grep()
+            .current_dir(root.join("src"))
+            .args(["-r", "--path-format", format, "hello", "../src/./net"])
+            .assert()
+            .success()
+    };
+
+    run("relative").stdout("http.rs:hello\n");
+    run("absolute").stdout(format!("{}:hello\n", root.join("src").join("net").join("http.rs").display()));
+    rusty_grep()
+        .current_dir(&root)
+        .args(["-r", "--path-format=relative", "hello", "src"])
+        .assert()
+        .success()
+        .stdout(format!("{}:hello\n", std::path::Path::new("net").join("http.rs").display()));
+    rusty_grep()
+        .current_dir(&root)
+        .args(["-H", "--path-format=relative", "hello", "src/net/http.rs"])
+        .assert()
+        .success()
+        .stdout("http.rs:hello\n");
+    rusty_grep()
+        .current_dir(root.join("src"))
+        .args(["-r", "hello", "../src/./net"])
+        .assert()
+        .success()
+        .stdout("../src/./net/http.rs:hello\n");
+}
+
+#[test]
+fn test_join_matches() {
+    rusty_grep()
+        .args(["-o", "--join-matches=|", "[0-9]+"])
+        .write_stdin("a 1 b 22 c 333\nnone\n4\n")
+        .assert()
+        .success()
+        .stdout("1|22|333\n4\n");
+    rusty_grep().args(["--join-matches=|", "[0-9]+"]).write_stdin("1\n").assert().code(2);
+}
+
+#[test]
+fn test_first_only() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), "nothing\n").unwrap();
+    std::fs::write(dir.path().join("b.txt"), "hello\n").unwrap();
+    std::fs::write(dir.path().join("c.txt"), "hello\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["-l", "--first-only", "hello", "a.txt", "b.txt", "c.txt", "missing.txt"])
+        .assert()
+        .success()
+        .stdout("b.txt\n")
+        .stderr("");
+    rusty_grep().args(["--first-only", "hello"]).write_stdin("hello\n").assert().code(2);
+}
+
+#[test]
+fn test_count_ignores_context() {
+    for context in [["-C", "3"], ["-A", "3"], ["-B", "3"]] {
+        rusty_grep()
+            .arg("-c")
+            .args(context)
+            .arg("match")
+            .write_stdin("a\nmatch\nb\nc\nd\ne\nmatch\nf\n")
+            .assert()
+            .success()
+            .stdout("2\n");
+    }
+}
+
+#[test]
+fn test_max_count_invert_count() {
+    // Two matching lines and five that don't.
+    let input = "x1\nhit\nx2\nx3\nhit\nx4\nx5\n";
+
+    rusty_grep().args(["-m", "2", "-v", "-c", "hit"]).write_stdin(input).assert().success().stdout("2\n");
+    rusty_grep().args(["-m", "9", "-v", "-c", "hit"]).write_stdin(input).assert().success().stdout("5\n");
+    rusty_grep().args(["-m", "1", "-c", "hit"]).write_stdin(input).assert().success().stdout("1\n");
+    rusty_grep().args(["-m", "2", "-v", "hit"]).write_stdin(input).assert().success().stdout("x1\nx2\n");
+    rusty_grep().args(["-m", "0", "-v", "-c", "--include-zero", "hit"]).write_stdin(input).assert().code(1).stdout("0\n");
+}
+
+#[test]
+fn test_count_null_layout() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a 1.txt"), "hello\nhello\n").unwrap();
+    std::fs::write(dir.path().join("b.txt"), "hello\n").unwrap();
+
+    let assert = rusty_grep().current_dir(dir.path()).args(["-c", "-Z", "hello", "a 1.txt", "b.txt"]).assert().success();
+
+    assert_eq!(assert.get_output().stdout, b"a 1.txt\x002\nb.txt\x001\n");
+}
+
+#[test]
+fn test_count_with_context() {
+    rusty_grep()
+        .args(["-C", "1", "--count-with-context", "match"])
+        .write_stdin("a\nmatch\nb\nmatch\nc\nd\ne\n")
+        .assert()
+        .success()
+        .stdout("5\n");
+}
+
+#[test]
+fn test_patterns_null() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("patterns"), "a.c\0start\nend\0").unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "abc\nstart\nend\nstart\nmiddle\n").unwrap();
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["-U", "-n", "--patterns-null", "-f", "patterns", "notes.txt"])
+        .assert()
+        .success()
+        .stdout("1:abc\n2:start\n3:end\n");
+}
+
+#[test]
+fn test_include_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    for sub in ["src", "tests", "target"] {
+        std::fs::create_dir(dir.path().join(sub)).unwrap();
+        std::fs::write(dir.path().join(sub).join("a.txt"), "hello\n").unwrap();
+    }
+
+    rusty_grep()
+        .current_dir(dir.path())
+        .args(["-r", "--include-dir=src", "--include-dir=t*", "--exclude-dir=target", "hello"])
+        .assert()
+        .success()
+        .stdout("./src/a.txt:hello\n./tests/a.txt:hello\n");
+}